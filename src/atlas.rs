@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Texture, TextureCreator};
+
+use crate::AtlasIndexKey;
+
+/// Largest atlas side, in pixels. Enough for thousands of glyphs at usual font sizes.
+const ATLAS_SIZE: u32 = 2048;
+
+struct AtlasEntry {
+    slot: u32,
+    width: u32,
+    last_used: u64,
+}
+
+/// A texture holding rendered glyphs in a grid of fixed-size slots, wide enough for a
+/// double-width character. When every slot is taken, the least recently used glyph is evicted to
+/// make room for a new one; it will be rendered again if it's needed later.
+pub struct GlyphAtlas {
+    pub texture: Texture,
+    entries: HashMap<AtlasIndexKey, AtlasEntry>,
+    free_slots: Vec<u32>,
+    slot_width: u32,
+    slot_height: u32,
+    columns: u32,
+    clock: u64,
+}
+
+impl GlyphAtlas {
+    /// `max_width` and `max_height` are the limits of the renderer, 0 meaning no limit.
+    pub fn new<T>(
+        texture_creator: &TextureCreator<T>,
+        max_width: u32,
+        max_height: u32,
+        font_width: u32,
+        font_height: u32,
+    ) -> GlyphAtlas {
+        let limit = |max: u32| {
+            if max > 0 {
+                std::cmp::min(max, ATLAS_SIZE)
+            } else {
+                ATLAS_SIZE
+            }
+        };
+        let slot_width = 2 * font_width;
+        let slot_height = font_height;
+        let columns = std::cmp::max(1, limit(max_width) / slot_width);
+        let rows = std::cmp::max(1, limit(max_height) / slot_height);
+        let mut texture = texture_creator
+            .create_texture_target(
+                PixelFormatEnum::ARGB8888,
+                columns * slot_width,
+                rows * slot_height,
+            )
+            .unwrap();
+        texture.set_blend_mode(BlendMode::Blend);
+        GlyphAtlas {
+            texture,
+            entries: HashMap::new(),
+            // Reversed so that slots get used from the top left corner
+            free_slots: (0..columns * rows).rev().collect(),
+            slot_width,
+            slot_height,
+            columns,
+            clock: 0,
+        }
+    }
+
+    fn slot_rect(&self, slot: u32, width: u32) -> Rect {
+        Rect::new(
+            ((slot % self.columns) * self.slot_width) as i32,
+            ((slot / self.columns) * self.slot_height) as i32,
+            width,
+            self.slot_height,
+        )
+    }
+
+    /// Where the glyph for `key` is in the texture, if it has been rendered.
+    pub fn get(&mut self, key: &AtlasIndexKey) -> Option<Rect> {
+        self.clock += 1;
+        let clock = self.clock;
+        let (slot, width) = self.entries.get_mut(key).map(|entry| {
+            entry.last_used = clock;
+            (entry.slot, entry.width)
+        })?;
+        Some(self.slot_rect(slot, width))
+    }
+
+    /// Reserves room for a glyph `width` pixels wide, evicting the least recently used glyph if
+    /// the atlas is full. Glyphs wider than a slot are cut.
+    pub fn allocate(&mut self, key: AtlasIndexKey, width: u32) -> Rect {
+        self.clock += 1;
+        let slot = match self.free_slots.pop() {
+            Some(slot) => slot,
+            None => {
+                let lru = *self
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.last_used)
+                    .map(|(key, _)| key)
+                    .unwrap();
+                self.entries.remove(&lru).unwrap().slot
+            }
+        };
+        let width = std::cmp::min(width, self.slot_width);
+        self.entries.insert(
+            key,
+            AtlasEntry {
+                slot,
+                width,
+                last_used: self.clock,
+            },
+        );
+        self.slot_rect(slot, width)
+    }
+}
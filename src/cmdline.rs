@@ -0,0 +1,169 @@
+use crate::interner::{Cells, Interner, EMPTY, SPACE};
+use crate::redraw::Chunk;
+
+/// The command line, drawn by nwin over the grid the cursor is in since neovim doesn't draw it
+/// when it's external.
+pub struct Cmdline {
+    /// The first character, prompt and indentation shown before the content
+    prefix: Cells,
+    content: Cells,
+    /// Byte offset in `text` of the character of each cell of `content`
+    offsets: Vec<usize>,
+    text: String,
+    /// Byte offset of the cursor in the content
+    pub pos: usize,
+    /// Nesting level, greater than 1 for cmdlines opened from another one, e.g. with <C-r>=
+    pub level: u64,
+    /// Character shown at the cursor while waiting for the rest of a key sequence, e.g. `"` after
+    /// <C-r>, and whether it's inserted before the cursor instead of covering it
+    pub special_char: Option<(Cells, bool)>,
+}
+
+impl Cmdline {
+    pub fn new(
+        content: &[Chunk],
+        pos: usize,
+        firstc: &str,
+        prompt: &str,
+        indent: usize,
+        level: u64,
+        interner: &mut Interner,
+    ) -> Cmdline {
+        let mut prefix: Cells = interner
+            .layout(&format!("{}{}", firstc, prompt))
+            .into_iter()
+            .map(|(_, text)| (text, 0))
+            .collect();
+        prefix.extend((0..indent).map(|_| (SPACE, 0)));
+        let mut cells = vec![];
+        let mut offsets = vec![];
+        let mut text = String::new();
+        for chunk in content {
+            for (offset, cell) in interner.layout(&chunk.text) {
+                cells.push((cell, chunk.attr_id));
+                offsets.push(text.len() + offset);
+            }
+            text.push_str(&chunk.text);
+        }
+        Cmdline {
+            prefix,
+            content: cells,
+            offsets,
+            text,
+            pos,
+            level,
+            special_char: None,
+        }
+    }
+
+    /// Byte offset of the start of the word before the cursor, where completion candidates go.
+    pub fn word_start(&self) -> usize {
+        let before = &self.text[..std::cmp::min(self.pos, self.text.len())];
+        before
+            .rfind(|c: char| c.is_whitespace())
+            .map_or(0, |space| space + 1)
+    }
+
+    /// Index in `cells()` of the cell of the character at byte offset `pos` of the content.
+    pub fn column(&self, pos: usize) -> usize {
+        self.prefix.len()
+            + self
+                .offsets
+                .iter()
+                .take_while(|offset| **offset < pos)
+                .count()
+    }
+
+    /// Number of cells scrolled past on the left so that the cursor fits in `cols` cells.
+    pub fn scroll(&self, cols: usize) -> usize {
+        let (cells, cursor) = self.cells();
+        let width = if cells.get(cursor + 1).map(|c| c.0) == Some(EMPTY) {
+            2
+        } else {
+            1
+        };
+        (cursor + width).saturating_sub(cols)
+    }
+
+    /// The cells to draw, and the index of the one the cursor is on. The prompt and first
+    /// character come before the indented content.
+    pub fn cells(&self) -> (Cells, usize) {
+        let mut cells = self.prefix.clone();
+        cells.extend_from_slice(&self.content);
+        let cursor = self.column(self.pos);
+        if let Some((special, shift)) = &self.special_char {
+            let end = if *shift {
+                cursor
+            } else {
+                std::cmp::min(cursor + special.len(), cells.len())
+            };
+            cells.splice(cursor..end, special.iter().copied());
+        }
+        (cells, cursor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmdline(text: &str, pos: usize, interner: &mut Interner) -> Cmdline {
+        let content = [Chunk {
+            attr_id: 1,
+            text: text.to_string(),
+        }];
+        Cmdline::new(&content, pos, ":", "", 0, 1, interner)
+    }
+
+    #[test]
+    fn double_width_characters_take_two_cells() {
+        let mut interner = Interner::new();
+        let cmdline = cmdline("漢字x", "漢字".len(), &mut interner);
+        let (cells, cursor) = cmdline.cells();
+        let texts: Vec<&str> = cells.iter().map(|c| interner.get(c.0)).collect();
+        assert_eq!(texts, [":", "漢", "", "字", "", "x"]);
+        assert_eq!(cursor, 5);
+        assert_eq!(cmdline.column("漢".len()), 3);
+        assert_eq!(cmdline.scroll(5), 1);
+    }
+
+    #[test]
+    fn combining_characters_share_a_cell() {
+        let mut interner = Interner::new();
+        let cmdline = cmdline("e\u{301}x", "e\u{301}".len(), &mut interner);
+        let (cells, cursor) = cmdline.cells();
+        assert_eq!(cells.len(), 3);
+        assert_eq!(interner.get(cells[1].0), "e\u{301}");
+        assert_eq!(cursor, 2);
+    }
+
+    #[test]
+    fn scrolling_keeps_double_width_cursors_visible() {
+        let mut interner = Interner::new();
+        let cmdline = cmdline("ab漢", 2, &mut interner);
+        assert_eq!(cmdline.cells().1, 3);
+        assert_eq!(cmdline.scroll(5), 0);
+        assert_eq!(cmdline.scroll(4), 1);
+    }
+
+    #[test]
+    fn special_characters_cover_or_shift_the_cursor() {
+        let mut interner = Interner::new();
+        let mut cmdline = cmdline("ab", 1, &mut interner);
+        let special = interner.cells(&[Chunk {
+            attr_id: 0,
+            text: "\"".to_string(),
+        }]);
+        cmdline.special_char = Some((special.clone(), false));
+        let texts = |cmdline: &Cmdline, interner: &Interner| -> Vec<String> {
+            let cells = cmdline.cells().0;
+            cells
+                .iter()
+                .map(|c| interner.get(c.0).to_string())
+                .collect()
+        };
+        assert_eq!(texts(&cmdline, &interner), [":", "a", "\""]);
+        cmdline.special_char = Some((special, true));
+        assert_eq!(texts(&cmdline, &interner), [":", "a", "\"", "b"]);
+    }
+}
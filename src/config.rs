@@ -0,0 +1,79 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Settings read from $XDG_CONFIG_HOME/nwin/config (~/.config/nwin/config by default). The file
+/// is made of `key = value` lines, lines starting with `#` are comments. Command line arguments
+/// take precedence over it.
+#[derive(Debug, Default)]
+pub struct Config {
+    /// Default font, in 'guifont' syntax. Neovim's 'guifont' takes precedence once set.
+    pub font: Option<String>,
+    /// Duration of the scrolling animation, in milliseconds. 0 disables it.
+    pub smooth_scroll: Option<u64>,
+    /// Whether messages are shown in their own window instead of over the current grid.
+    pub messages_window: Option<bool>,
+    /// Whether windows keep a row below the grid for 'showmode', 'showcmd' and 'ruler'. On by
+    /// default.
+    pub status_row: Option<bool>,
+}
+
+fn config_path() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => home::home_dir().map(|home| home.join(".config")),
+    }
+    .map(|dir| dir.join("nwin").join("config"))
+}
+
+impl Config {
+    pub fn load() -> Config {
+        let mut config = Config::default();
+        let path = match config_path() {
+            Some(path) => path,
+            None => return config,
+        };
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => return config,
+        };
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+                None => {
+                    eprintln!("{}:{}: expected key = value", path.display(), number + 1);
+                    continue;
+                }
+            };
+            match key {
+                "font" => config.font = Some(value.to_string()),
+                "smooth_scroll" => match value.parse() {
+                    Ok(ms) => config.smooth_scroll = Some(ms),
+                    Err(_) => eprintln!(
+                        "{}:{}: expected a number of milliseconds",
+                        path.display(),
+                        number + 1
+                    ),
+                },
+                "messages_window" => match value.parse() {
+                    Ok(enabled) => config.messages_window = Some(enabled),
+                    Err(_) => {
+                        eprintln!("{}:{}: expected true or false", path.display(), number + 1)
+                    }
+                },
+                "status_row" => match value.parse() {
+                    Ok(enabled) => config.status_row = Some(enabled),
+                    Err(_) => {
+                        eprintln!("{}:{}: expected true or false", path.display(), number + 1)
+                    }
+                },
+                _ => eprintln!("{}:{}: unknown key '{}'", path.display(), number + 1, key),
+            }
+        }
+        config
+    }
+}
@@ -0,0 +1,295 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use sdl2::ttf::{Font, FontStyle, Sdl2TtfContext};
+
+pub const DEFAULT_FONT: &str = "Noto Sans Mono:h16";
+
+/// A font as described by 'guifont', e.g. `Noto_Sans_Mono:h12,Noto_Color_Emoji`. The first
+/// font of the comma-separated list is the primary font, the other ones are used for the
+/// characters it lacks. Only the height option of the primary font is used.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontSpec {
+    pub family: String,
+    pub size: u16,
+    pub fallbacks: Vec<String>,
+}
+
+impl FontSpec {
+    pub fn parse(guifont: &str) -> Result<FontSpec, String> {
+        let mut fonts = guifont.split(',').map(|f| f.trim());
+        let first = fonts.next().unwrap_or("");
+        let mut parts = first.split(':');
+        let family = parts.next().unwrap_or("").replace('_', " ");
+        if family.is_empty() {
+            return Err(format!("No font family in '{}'", guifont));
+        }
+        let mut size = 16;
+        for option in parts {
+            if let Some(height) = option.strip_prefix('h') {
+                size = height
+                    .parse::<f64>()
+                    .map_err(|_| format!("Invalid font height in '{}'", guifont))?
+                    .round() as u16;
+            }
+            // Other options (b, i, w<width>...) don't make sense for nwin, which picks faces
+            // according to highlights and sizes cells according to the font.
+        }
+        let fallbacks = fonts
+            .filter_map(|f| f.split(':').next())
+            .filter(|f| !f.is_empty())
+            .map(|f| f.replace('_', " "))
+            .collect();
+        Ok(FontSpec {
+            family,
+            size,
+            fallbacks,
+        })
+    }
+}
+
+/// Lowercases `name` and strips the separators that commonly differ between family names and
+/// file names, so that "Noto Sans Mono" matches NotoSansMono-Regular.ttf.
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, ' ' | '_' | '-'))
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+/// Directories fonts are usually installed in, starting with the place where nwin used to
+/// require fonts to be.
+fn font_directories() -> Vec<PathBuf> {
+    let mut dirs = vec![];
+    if let Some(home) = home::home_dir() {
+        dirs.push(home.join("downloads/NotoSansMono"));
+        dirs.push(home.join(".local/share/fonts"));
+        dirs.push(home.join(".fonts"));
+    }
+    if let Some(data_home) = env::var_os("XDG_DATA_HOME") {
+        dirs.push(PathBuf::from(data_home).join("fonts"));
+    }
+    dirs.push(PathBuf::from("/usr/local/share/fonts"));
+    dirs.push(PathBuf::from("/usr/share/fonts"));
+    dirs
+}
+
+fn scan_directory(dir: &Path, family: &str) -> Option<PathBuf> {
+    let mut subdirs = vec![];
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            subdirs.push(path);
+            continue;
+        }
+        let is_font = matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("ttf") | Some("otf") | Some("TTF") | Some("OTF")
+        );
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        let (name, face) = match stem.rfind('-') {
+            Some(i) => (&stem[..i], &stem[i + 1..]),
+            None => (stem, "Regular"),
+        };
+        if is_font && face == "Regular" && normalize(name) == family {
+            return Some(path);
+        }
+    }
+    subdirs
+        .iter()
+        .filter_map(|dir| scan_directory(dir, family))
+        .next()
+}
+
+/// Returns the path of the regular face of `family`. `family` can also be the path of a font
+/// file. Font directories are scanned first, then fontconfig is asked. Fontconfig always finds
+/// something, even if it's unrelated to what was asked for, so its font is only used if it
+/// belongs to `family`.
+pub fn find_font(family: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(family);
+    if path.is_file() {
+        return Some(path);
+    }
+    let normalized = normalize(family);
+    if let Some(path) = font_directories()
+        .iter()
+        .filter_map(|dir| scan_directory(dir, &normalized))
+        .next()
+    {
+        return Some(path);
+    }
+    let output = Command::new("fc-match")
+        .arg("--format=%{family}\\n%{file}")
+        .arg(format!("{}:style=Regular", family))
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let path = matching_font(&String::from_utf8_lossy(&output.stdout), &normalized)?;
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// The file of the font fc-match found, given its `family` and `file` as printed by
+/// `--format=%{family}\n%{file}`, if it's one of the families of the font. `family` is
+/// normalized.
+fn matching_font(output: &str, family: &str) -> Option<PathBuf> {
+    let mut lines = output.splitn(2, '\n');
+    // Fonts can belong to several families, e.g. "DejaVu Sans,DejaVu Sans Condensed"
+    let families = lines.next()?;
+    let file = lines.next()?.trim();
+    if families.split(',').any(|f| normalize(f) == family) {
+        Some(PathBuf::from(file))
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Style {
+    Regular,
+    Bold,
+    Italic,
+    BoldItalic,
+}
+
+impl Style {
+    pub fn new(bold: bool, italic: bool) -> Style {
+        match (bold, italic) {
+            (false, false) => Style::Regular,
+            (true, false) => Style::Bold,
+            (false, true) => Style::Italic,
+            (true, true) => Style::BoldItalic,
+        }
+    }
+}
+
+/// The faces of a font family. Faces that can't be found next to the regular one (e.g.
+/// NotoSansMono-Bold.ttf next to NotoSansMono-Regular.ttf) are synthesized by SDL_ttf from the
+/// regular face. Characters missing from the family are looked up in the fallback fonts, in
+/// order.
+pub struct Fonts<'ttf> {
+    regular: Font<'ttf, 'static>,
+    bold: Font<'ttf, 'static>,
+    italic: Font<'ttf, 'static>,
+    bold_italic: Font<'ttf, 'static>,
+    fallbacks: Vec<Font<'ttf, 'static>>,
+}
+
+/// Returns the path of the `face` variant of the font at `path`, if there is one.
+pub fn face_path(path: &Path, face: &str) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    if !name.contains("-Regular") {
+        return None;
+    }
+    let face_path = path.with_file_name(name.replace("-Regular", &format!("-{}", face)));
+    if face_path.exists() {
+        Some(face_path)
+    } else {
+        None
+    }
+}
+
+fn load_face<'ttf>(
+    ttf: &'ttf Sdl2TtfContext,
+    path: &Path,
+    size: u16,
+    face: &str,
+    synthesized: FontStyle,
+) -> Result<Font<'ttf, 'static>, String> {
+    if let Some(face_path) = face_path(path, face) {
+        return ttf.load_font(face_path, size);
+    }
+    let mut font = ttf.load_font(path, size)?;
+    font.set_style(synthesized);
+    Ok(font)
+}
+
+impl<'ttf> Fonts<'ttf> {
+    pub fn load(ttf: &'ttf Sdl2TtfContext, path: &Path, size: u16) -> Result<Fonts<'ttf>, String> {
+        Ok(Fonts {
+            regular: ttf.load_font(path, size)?,
+            bold: load_face(ttf, path, size, "Bold", FontStyle::BOLD)?,
+            italic: load_face(ttf, path, size, "Italic", FontStyle::ITALIC)?,
+            bold_italic: load_face(
+                ttf,
+                path,
+                size,
+                "BoldItalic",
+                FontStyle::BOLD | FontStyle::ITALIC,
+            )?,
+            fallbacks: vec![],
+        })
+    }
+
+    pub fn from_spec(ttf: &'ttf Sdl2TtfContext, spec: &FontSpec) -> Result<Fonts<'ttf>, String> {
+        let path = find_font(&spec.family)
+            .ok_or_else(|| format!("Can't find font '{}', check README.md", spec.family))?;
+        let mut fonts = Fonts::load(ttf, &path, spec.size)?;
+        for family in &spec.fallbacks {
+            // A missing fallback font only means that some characters will be missing
+            match find_font(family).map(|path| ttf.load_font(path, spec.size)) {
+                Some(Ok(font)) => fonts.fallbacks.push(font),
+                Some(Err(e)) => eprintln!("Can't load fallback font '{}': {}", family, e),
+                None => eprintln!("Can't find fallback font '{}'", family),
+            }
+        }
+        Ok(fonts)
+    }
+
+    /// The size of grid cells, `linespace` being the number of pixels between lines.
+    pub fn cell_size(&self, linespace: i64) -> Result<(u32, u32), String> {
+        let (width, height) = self.regular.size_of("A").map_err(|e| e.to_string())?;
+        Ok((width, std::cmp::max(1, height as i64 + linespace) as u32))
+    }
+
+    pub fn get(&self, style: Style) -> &Font<'ttf, 'static> {
+        match style {
+            Style::Regular => &self.regular,
+            Style::Bold => &self.bold,
+            Style::Italic => &self.italic,
+            Style::BoldItalic => &self.bold_italic,
+        }
+    }
+
+    /// The font `c` should be rendered with, and whether it's a fallback font. Falls back to the
+    /// primary font if no font has a glyph for `c`.
+    pub fn for_char(&self, c: char, style: Style) -> (&Font<'ttf, 'static>, bool) {
+        let font = self.get(style);
+        if font.find_glyph(c).is_some() {
+            return (font, false);
+        }
+        match self.fallbacks.iter().find(|f| f.find_glyph(c).is_some()) {
+            Some(fallback) => (fallback, true),
+            None => (font, false),
+        }
+    }
+
+    pub fn regular(&self) -> &Font<'ttf, 'static> {
+        &self.regular
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fontconfig_fonts_must_belong_to_the_family() {
+        let family = normalize("DejaVu Sans Mono");
+        let output = "DejaVu Sans Mono,DejaVu Sans Mono Book\n/usr/share/fonts/DejaVuSansMono.ttf";
+        assert_eq!(
+            matching_font(output, &family),
+            Some(PathBuf::from("/usr/share/fonts/DejaVuSansMono.ttf"))
+        );
+        let unrelated = "DejaVu Sans\n/usr/share/fonts/DejaVuSans.ttf";
+        assert_eq!(matching_font(unrelated, &family), None);
+        assert_eq!(matching_font("", &family), None);
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::Duration;
+
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::surface::Surface;
+
+use crate::fonts::Fonts;
+use crate::renderer::SDLGrid;
+use crate::{NvimGridId, NvimState};
+
+/// Renders NvimGrids into offscreen surfaces instead of OS windows. This only needs SDL's
+/// software renderer, which makes it usable on machines without a GPU or a display (e.g. with
+/// SDL_VIDEODRIVER=dummy).
+pub struct HeadlessRenderer {
+    grids: HashMap<NvimGridId, SDLGrid<Surface<'static>>>,
+    font_width: u32,
+    font_height: u32,
+}
+
+impl HeadlessRenderer {
+    pub fn new(font_width: u32, font_height: u32) -> HeadlessRenderer {
+        HeadlessRenderer {
+            grids: HashMap::new(),
+            font_width,
+            font_height,
+        }
+    }
+
+    fn create_grid(&self, width: u32, height: u32) -> Result<SDLGrid<Surface<'static>>, String> {
+        let surface = Surface::new(width, height, PixelFormatEnum::ARGB8888)?;
+        let canvas = surface.into_canvas()?;
+        let texture_creator = canvas.texture_creator();
+        Ok(SDLGrid::from_canvas(
+            canvas,
+            texture_creator,
+            width,
+            height,
+            self.font_width,
+            self.font_height,
+        ))
+    }
+
+    /// Draws the damages of every grid in `state`. Surfaces can't be resized, so grids whose size
+    /// changed get a new one. This is fine because neovim always sends a grid_resize (and thus a
+    /// full damage) for these.
+    pub fn render(
+        &mut self,
+        state: &NvimState,
+        skip: &[NvimGridId],
+        fonts: &Fonts,
+    ) -> Result<(), String> {
+        for (key, grid) in state.grids.iter() {
+            if skip.contains(key) {
+                continue;
+            }
+            let width = std::cmp::max(1, grid.get_width() as u32 * self.font_width);
+            let height = std::cmp::max(1, grid.get_height() as u32 * self.font_height);
+            let needs_new_surface = match self.grids.get(key) {
+                Some(g) => g.width != width || g.height != height,
+                None => true,
+            };
+            if needs_new_surface {
+                let sdl_grid = self.create_grid(width, height)?;
+                self.grids.insert(*key, sdl_grid);
+            }
+            let sdl_grid = self.grids.get_mut(key).unwrap();
+            // Only the final state of grids is saved, there's nothing to animate
+            sdl_grid.draw(
+                grid,
+                *key == state.cursor_grid,
+                state,
+                fonts,
+                Duration::from_secs(0),
+            );
+            sdl_grid.canvas.present();
+        }
+        Ok(())
+    }
+
+    /// Grids are drawn again from scratch with the new cell size on the next render().
+    pub fn set_font_size(&mut self, font_width: u32, font_height: u32) {
+        self.font_width = font_width;
+        self.font_height = font_height;
+        self.grids.clear();
+    }
+
+    pub fn remove(&mut self, id: NvimGridId) {
+        self.grids.remove(&id);
+    }
+
+    /// Writes the last frame rendered for grid `id` to `path` as a PNG file.
+    pub fn save_png(&self, id: NvimGridId, path: &Path) -> Result<(), String> {
+        let sdl_grid = self
+            .grids
+            .get(&id)
+            .ok_or_else(|| format!("No grid {} to save", id))?;
+        let (width, height) = (sdl_grid.width, sdl_grid.height);
+        let pixels = sdl_grid
+            .canvas
+            .read_pixels(Rect::new(0, 0, width, height), PixelFormatEnum::RGBA32)?;
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+        writer.write_image_data(&pixels).map_err(|e| e.to_string())
+    }
+
+    /// Writes every grid to `dir`/grid-<id>.png.
+    pub fn save_all(&self, dir: &Path) -> Result<(), String> {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        for id in self.grids.keys() {
+            self.save_png(*id, &dir.join(format!("grid-{}.png", id)))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::VecDeque;
+    use std::process;
+
+    use neovim_lib::Value;
+
+    use crate::fonts::{self, FontSpec};
+
+    const GRID: NvimGridId = 1;
+    const RED: [u8; 3] = [255, 0, 0];
+    const BLUE: [u8; 3] = [0, 0, 255];
+
+    fn event(name: &str, args: Vec<Value>) -> Value {
+        Value::Array(vec![Value::from(name), Value::Array(args)])
+    }
+
+    fn cell(text: &str, hl_id: u64, repeat: u64) -> Value {
+        Value::Array(vec![
+            Value::from(text),
+            Value::from(hl_id),
+            Value::from(repeat),
+        ])
+    }
+
+    /// A 4x2 grid on a blue background whose first row starts with two red cells, the second
+    /// one holding a character.
+    fn redraw(state: &mut NvimState) {
+        let red = vec![(Value::from("background"), Value::from(0xff0000))];
+        let mut batches: VecDeque<Value> = vec![
+            event(
+                "default_colors_set",
+                vec![
+                    Value::from(0xffffff),
+                    Value::from(0x0000ff),
+                    Value::from(0xffffff),
+                ],
+            ),
+            event(
+                "hl_attr_define",
+                vec![
+                    Value::from(1),
+                    Value::Map(red),
+                    Value::Map(vec![]),
+                    Value::Array(vec![]),
+                ],
+            ),
+            event(
+                "grid_resize",
+                vec![Value::from(GRID), Value::from(4), Value::from(2)],
+            ),
+            event(
+                "grid_line",
+                vec![
+                    Value::from(GRID),
+                    Value::from(0),
+                    Value::from(0),
+                    Value::Array(vec![cell(" ", 1, 1), cell("x", 1, 1)]),
+                ],
+            ),
+        ]
+        .into();
+        crate::do_redraw(state, None, batches.drain(..));
+    }
+
+    /// The RGB components of the pixel at `x`, `y` of RGBA `pixels`.
+    fn pixel(pixels: &[u8], width: u32, x: u32, y: u32) -> [u8; 3] {
+        let i = 4 * (y * width + x) as usize;
+        [pixels[i], pixels[i + 1], pixels[i + 2]]
+    }
+
+    #[test]
+    fn renders_redraw_events() {
+        std::env::set_var("SDL_VIDEODRIVER", "dummy");
+        let spec = FontSpec::parse(fonts::DEFAULT_FONT).unwrap();
+        let path = [spec.family.as_str(), "DejaVu Sans Mono", "Liberation Mono"]
+            .iter()
+            .find_map(|family| fonts::find_font(family))
+            .expect("rendering needs Noto Sans Mono, DejaVu Sans Mono or Liberation Mono");
+        let sdl = sdl2::init().unwrap();
+        let _video = sdl.video().unwrap();
+        let ttf = sdl2::ttf::init().unwrap();
+        let fonts = Fonts::load(&ttf, &path, spec.size).unwrap();
+        let (font_width, font_height) = fonts.cell_size(0).unwrap();
+
+        let mut state = NvimState::new();
+        redraw(&mut state);
+        let mut renderer = HeadlessRenderer::new(font_width, font_height);
+        renderer.render(&state, &[], &fonts).unwrap();
+
+        let grid = &renderer.grids[&GRID];
+        assert_eq!((grid.width, grid.height), (4 * font_width, 2 * font_height));
+        let pixels = grid
+            .canvas
+            .read_pixels(
+                Rect::new(0, 0, grid.width, grid.height),
+                PixelFormatEnum::RGBA32,
+            )
+            .unwrap();
+        let center = |column: u32, row: u32| {
+            let x = column * font_width + font_width / 2;
+            let y = row * font_height + font_height / 2;
+            pixel(&pixels, grid.width, x, y)
+        };
+        assert_eq!(center(0, 0), RED);
+        assert_eq!(center(2, 0), BLUE);
+        assert_eq!(center(3, 1), BLUE);
+        // The character is drawn in the foreground color over its cell's background
+        let second_cell: Vec<[u8; 3]> = (0..font_height)
+            .flat_map(|y| (font_width..2 * font_width).map(move |x| (x, y)))
+            .map(|(x, y)| pixel(&pixels, grid.width, x, y))
+            .collect();
+        assert!(second_cell.contains(&RED));
+        assert!(second_cell.iter().any(|p| p[1] > 0));
+
+        let path = std::env::temp_dir().join(format!("nwin-{}-headless.png", process::id()));
+        renderer.save_png(GRID, &path).unwrap();
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut png = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut png).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(png, pixels);
+    }
+}
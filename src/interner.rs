@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+#[cfg(feature = "harfbuzz")]
+use std::collections::HashSet;
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::redraw::Chunk;
+
+/// Cells hold the id of their text instead of the text itself: most grids only ever contain a few
+/// hundred distinct strings, and ids are cheap to copy around when scrolling.
+pub type CellText = u32;
+
+/// The text of the cell following a double-width character.
+pub const EMPTY: CellText = 0;
+pub const SPACE: CellText = 1;
+
+/// Text nwin lays out itself, e.g. the cmdline or messages: the text of each cell and its
+/// highlight id. As in grids, double-width characters are followed by an EMPTY cell.
+pub type Cells = Vec<(CellText, u64)>;
+
+/// Set in the ids of shaped clusters. Clusters come and go as text is edited, so they have their
+/// own table, which forgets the clusters grids don't display anymore.
+#[cfg(feature = "harfbuzz")]
+const CLUSTER: CellText = 1 << 31;
+
+/// Clusters kept before looking for the ones that can be forgotten.
+#[cfg(feature = "harfbuzz")]
+const MIN_CLUSTERS: usize = 1024;
+
+pub struct Interner {
+    ids: HashMap<String, CellText>,
+    strings: Vec<String>,
+    #[cfg(feature = "harfbuzz")]
+    cluster_ids: HashMap<String, CellText>,
+    #[cfg(feature = "harfbuzz")]
+    clusters: HashMap<CellText, String>,
+    #[cfg(feature = "harfbuzz")]
+    next_cluster: CellText,
+    /// Number of clusters past which the unused ones are forgotten
+    #[cfg(feature = "harfbuzz")]
+    sweep_at: usize,
+}
+
+impl Interner {
+    pub fn new() -> Interner {
+        let mut interner = Interner {
+            ids: HashMap::new(),
+            strings: vec![],
+            #[cfg(feature = "harfbuzz")]
+            cluster_ids: HashMap::new(),
+            #[cfg(feature = "harfbuzz")]
+            clusters: HashMap::new(),
+            #[cfg(feature = "harfbuzz")]
+            next_cluster: 0,
+            #[cfg(feature = "harfbuzz")]
+            sweep_at: MIN_CLUSTERS,
+        };
+        interner.intern("");
+        interner.intern(" ");
+        interner
+    }
+
+    pub fn intern(&mut self, text: &str) -> CellText {
+        if let Some(id) = self.ids.get(text) {
+            return *id;
+        }
+        let id = self.strings.len() as CellText;
+        self.strings.push(text.to_string());
+        self.ids.insert(text.to_string(), id);
+        id
+    }
+
+    pub fn get(&self, id: CellText) -> &str {
+        #[cfg(feature = "harfbuzz")]
+        if id & CLUSTER != 0 {
+            return &self.clusters[&id];
+        }
+        &self.strings[id as usize]
+    }
+
+    /// Interns the text of a shaped cluster. The ids of forgotten clusters aren't reused, so that
+    /// the glyphs rendered for them can't be mistaken for the ones of new clusters.
+    #[cfg(feature = "harfbuzz")]
+    pub fn intern_cluster(&mut self, text: &str) -> CellText {
+        if let Some(id) = self.cluster_ids.get(text) {
+            return *id;
+        }
+        let id = CLUSTER | self.next_cluster;
+        self.next_cluster = self.next_cluster.wrapping_add(1) & !CLUSTER;
+        self.clusters.insert(id, text.to_string());
+        self.cluster_ids.insert(text.to_string(), id);
+        id
+    }
+
+    /// Whether enough clusters were interned since the last sweep to look for unused ones.
+    #[cfg(feature = "harfbuzz")]
+    pub fn should_sweep_clusters(&self) -> bool {
+        self.clusters.len() >= self.sweep_at
+    }
+
+    /// Forgets the clusters that aren't in `used`.
+    #[cfg(feature = "harfbuzz")]
+    pub fn sweep_clusters(&mut self, used: &HashSet<CellText>) {
+        self.clusters.retain(|id, _| used.contains(id));
+        let clusters = &self.clusters;
+        self.cluster_ids.retain(|_, id| clusters.contains_key(id));
+        self.sweep_at = std::cmp::max(MIN_CLUSTERS, 2 * self.clusters.len());
+    }
+
+    /// Splits `text` into cells the way neovim fills grids: double-width characters take two
+    /// cells and combining characters share the cell of the character they follow. Returns the
+    /// text of each cell along with the byte offset of its character in `text`.
+    pub fn layout(&mut self, text: &str) -> Vec<(usize, CellText)> {
+        let mut chars: Vec<(usize, String, usize)> = vec![];
+        for (offset, c) in text.char_indices() {
+            match (c.width(), chars.last_mut()) {
+                (Some(0), Some((_, previous, _))) => previous.push(c),
+                (width, _) => chars.push((offset, c.to_string(), width.unwrap_or(1).max(1))),
+            }
+        }
+        let mut cells = vec![];
+        for (offset, text, width) in chars {
+            cells.push((offset, self.intern(&text)));
+            if width > 1 {
+                cells.push((offset, EMPTY));
+            }
+        }
+        cells
+    }
+
+    /// The cells of `chunks`, each one keeping the highlight of its chunk.
+    pub fn cells(&mut self, chunks: &[Chunk]) -> Cells {
+        let mut cells = vec![];
+        for chunk in chunks {
+            let layout = self.layout(&chunk.text);
+            cells.extend(layout.into_iter().map(|(_, text)| (text, chunk.attr_id)));
+        }
+        cells
+    }
+}
@@ -2,6 +2,7 @@ extern crate sdl2;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::keyboard::Mod;
+use sdl2::mouse::MouseButton;
 
 // Issues:
 // 1) `/` is actually `:`+`Shift`. We need to catch this as returning `<S-:>` would result in `:/`
@@ -50,6 +51,34 @@ fn with_mod(s: &str, m: Mod) -> Option<String> {
     Some(result)
 }
 
+/// The modifiers of a mouse event, as expected by nvim_input_mouse (e.g. "C-S-").
+pub fn nvim_mouse_modifiers(m: Mod) -> String {
+    let mut result = String::new();
+    if m.intersects(Mod::LGUIMOD | Mod::RGUIMOD) {
+        result.push_str("D-");
+    }
+    if m.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) {
+        result.push_str("C-");
+    }
+    // RALT is ignored for the same reason as in with_mod
+    if m.intersects(Mod::LALTMOD) {
+        result.push_str("A-");
+    }
+    if m.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+        result.push_str("S-");
+    }
+    result
+}
+
+pub fn nvim_mouse_button(button: MouseButton) -> Option<&'static str> {
+    match button {
+        MouseButton::Left => Some("left"),
+        MouseButton::Right => Some("right"),
+        MouseButton::Middle => Some("middle"),
+        _ => None,
+    }
+}
+
 pub fn nvim_char_representation(c: char) -> Option<&'static str> {
     match c {
         '<' => Some("<LT>"),
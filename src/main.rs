@@ -1,35 +1,63 @@
+mod atlas;
+mod cmdline;
+mod config;
+mod fonts;
+mod headless;
+mod interner;
 mod keys;
+mod messages;
+mod popupmenu;
+mod recording;
+mod redraw;
+mod renderer;
+#[cfg(feature = "harfbuzz")]
+mod shaping;
+mod title;
+mod wm;
 
-use swayipc::{Connection, NodeLayout};
+use wm::{Layout, Split, WindowManager};
 
-use std::process::Command;
+use std::process::{self, Command};
 
 use std::collections::vec_deque::Drain;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::convert::TryFrom;
-use std::convert::TryInto;
 use std::env;
+use std::path::{Path, PathBuf};
 use std::string::String;
-use std::time::Instant;
+use std::sync::mpsc::TryRecvError;
+use std::time::{Duration, Instant};
 
 extern crate sdl2;
 
 use sdl2::event::{Event, WindowEvent};
+use sdl2::mouse::MouseWheelDirection;
 use sdl2::pixels::Color;
-use sdl2::pixels::PixelFormatEnum;
-use sdl2::rect::Rect;
-use sdl2::render::BlendMode;
-use sdl2::render::Canvas;
-use sdl2::render::Texture;
-use sdl2::render::TextureCreator;
-use sdl2::video::Window;
-use sdl2::video::WindowContext;
-use sdl2::VideoSubsystem;
 
 use neovim_lib::{Neovim, NeovimApi, Session, UiAttachOptions, Value};
 
-type AtlasIndexKey = char;
+use cmdline::Cmdline;
+use config::Config;
+use fonts::{FontSpec, Fonts, Style};
+use headless::HeadlessRenderer;
+use interner::{CellText, Cells, Interner, SPACE};
+use messages::{Message, Messages};
+use popupmenu::Popupmenu;
+use recording::{Recorder, RecordingHeader};
+use redraw::{
+    Chunk, GridLineCell, HlAttr, ModeInfo, PopupmenuItem, RedrawEvent, SplitDirection, UiOption,
+};
+use renderer::{window_title, MessagesWindow, RenderDriver, SDLGrid};
+
+/// The text of a cell, or of the shaped cluster it's part of, its style and the index of the cell
+/// in the cluster (0 outside of clusters).
+type AtlasIndexKey = (CellText, Style, u16);
+/// The part of a shaped cluster a cell displays: the text of all the cells of the cluster and the
+/// index of the cell in the cluster. The text of clusters is interned apart from the text of
+/// cells, see Interner::intern_cluster(), so the two can't be mistaken for each other.
+type ClusterSlice = (CellText, u16);
 type NvimRow = usize;
 type NvimColumn = usize;
 type NvimWidth = usize;
@@ -37,38 +65,6 @@ type NvimHeight = usize;
 type NvimGridId = u64;
 type NvimWinId = u64;
 
-#[derive(Debug)]
-pub enum SplitDirection {
-    Above = 0,
-    Below = 1,
-    Left = 2,
-    Right = 3,
-    BelowRight = 4,
-    AboveLeft = 5,
-    TopLeft = 6,
-    BottomRight = 7,
-    Previous = 8,
-}
-
-impl TryFrom<u64> for SplitDirection {
-    type Error = ();
-
-    fn try_from(v: u64) -> Result<Self, Self::Error> {
-        match v {
-            x if x == SplitDirection::Above as u64 => Ok(SplitDirection::Above),
-            x if x == SplitDirection::Below as u64 => Ok(SplitDirection::Below),
-            x if x == SplitDirection::Left as u64 => Ok(SplitDirection::Left),
-            x if x == SplitDirection::Right as u64 => Ok(SplitDirection::Right),
-            x if x == SplitDirection::BelowRight as u64 => Ok(SplitDirection::BelowRight),
-            x if x == SplitDirection::AboveLeft as u64 => Ok(SplitDirection::AboveLeft),
-            x if x == SplitDirection::TopLeft as u64 => Ok(SplitDirection::TopLeft),
-            x if x == SplitDirection::BottomRight as u64 => Ok(SplitDirection::BottomRight),
-            x if x == SplitDirection::Previous as u64 => Ok(SplitDirection::Previous),
-            _ => Err(()),
-        }
-    }
-}
-
 #[derive(Debug)]
 enum Damage {
     Cell {
@@ -78,43 +74,58 @@ enum Damage {
         height: NvimHeight,
     },
     Destroy {},
-    VerticalScroll {
-        to_row: NvimRow,
+    /// The `width`x`height` cells at `from_row`, `from_col` moved to `to_row`, `to_col`.
+    Scroll {
         from_row: NvimRow,
-        height: NvimHeight,
         from_col: NvimColumn,
+        to_row: NvimRow,
+        to_col: NvimColumn,
         width: NvimWidth,
+        height: NvimHeight,
     },
 }
 
 pub struct NvimGrid {
-    chars: Vec<Vec<Option<char>>>,
+    cells: Vec<Vec<CellText>>,
     colors: Vec<Vec<u64>>,
+    /// Only filled when nwin is built with the harfbuzz feature
+    clusters: Vec<Vec<Option<ClusterSlice>>>,
     cursor: (NvimRow, NvimColumn),
     damages: Vec<Damage>,
     window_id: NvimWinId,
+    buffer_name: String,
+    modified: bool,
+    topline: u64,
+    /// Whether the window scrolled since the grid was last drawn. Scrolls that come from
+    /// scrolling the window, as opposed to e.g. deleting lines, are animated.
+    viewport_scrolled: bool,
 }
 
 impl NvimGrid {
     pub fn new(width: NvimWidth, height: NvimHeight) -> NvimGrid {
         NvimGrid {
-            chars: vec![vec![Some(' '); width]; height],
+            cells: vec![vec![SPACE; width]; height],
             colors: vec![vec![0; width]; height],
+            clusters: vec![vec![None; width]; height],
             cursor: (0, 0),
             damages: vec![],
             window_id: 0,
+            buffer_name: String::new(),
+            modified: false,
+            topline: 0,
+            viewport_scrolled: false,
         }
     }
     pub fn get_height(&self) -> NvimHeight {
-        assert!(self.chars.len() == self.colors.len());
-        self.chars.len() as NvimHeight
+        assert!(self.cells.len() == self.colors.len());
+        self.cells.len() as NvimHeight
     }
     pub fn get_width(&self) -> NvimWidth {
-        (if self.chars.len() < 1 {
+        (if self.cells.len() < 1 {
             0
         } else {
-            assert!(self.chars[0].len() == self.colors[0].len());
-            self.chars[0].len()
+            assert!(self.cells[0].len() == self.colors[0].len());
+            self.cells[0].len()
         }) as NvimWidth
     }
     pub fn get_cursor_pos(&self) -> (NvimRow, NvimColumn) {
@@ -131,6 +142,22 @@ impl NvimGrid {
         self.cursor.0 = row;
         self.cursor.1 = column;
     }
+    /// What the cell at `row`, `column` displays: its own text or a slice of its cluster.
+    pub fn glyph(&self, row: NvimRow, column: NvimColumn) -> ClusterSlice {
+        self.clusters[row][column].unwrap_or((self.cells[row][column], 0))
+    }
+}
+
+/// Where the part of `start..end` that stays visible when scrolling it by `delta` comes from,
+/// where it goes and how long it is. A positive `delta` moves cells towards `start`.
+fn scrolled_span(start: usize, end: usize, delta: i64) -> (usize, usize, usize) {
+    let distance = delta.unsigned_abs() as usize;
+    let length = end.saturating_sub(start).saturating_sub(distance);
+    if delta >= 0 {
+        (start + distance, start, length)
+    } else {
+        (start, start + distance, length)
+    }
 }
 
 fn to_sdl_color(color: u64) -> Color {
@@ -171,20 +198,44 @@ impl NvimHighlightAttribute {
     }
 }
 
+#[derive(Debug)]
+pub struct PendingSplit {
+    parent: NvimWinId,
+    child: NvimWinId,
+    direction: SplitDirection,
+}
+
 pub struct NvimState {
     grids: HashMap<NvimGridId, NvimGrid>,
     hl_attrs: HashMap<u64, NvimHighlightAttribute>,
     cursor_grid: NvimGridId,
-    cmdline_content: String,
-    cmdline_firstc: char,
-    cmdline_pos: u64,
-    cmdline_prompt: String,
-    cmdline_shown: bool,
+    cmdline: Option<Cmdline>,
+    /// Lines entered so far in a multi-line command, e.g. a :function typed in the cmdline
+    cmdline_block: Vec<Cells>,
     cursor_on: bool,
-    message_attrs: Vec<u64>,
-    message_contents: Vec<String>,
-    message_time: Instant,
-    has_moved_since_last_message: bool,
+    mouse_enabled: bool,
+    messages: Messages,
+    /// Shown in the status strip of the cursor grid, see 'showmode', 'showcmd' and 'ruler'
+    showmode: Cells,
+    showcmd: Cells,
+    ruler: Cells,
+    /// Highlight ids of the builtin groups used by UI elements, e.g. "Pmenu"
+    hl_groups: HashMap<String, u64>,
+    popupmenu: Option<Popupmenu>,
+    pending_splits: VecDeque<PendingSplit>,
+    pending_splits_since: Instant,
+    title: String,
+    buffer_info_outdated: bool,
+    cursor_style_enabled: bool,
+    mode_infos: Vec<ModeInfo>,
+    mode_idx: usize,
+    cursor_moved_at: Instant,
+    guifont: String,
+    linespace: i64,
+    font_changed: bool,
+    cell_texts: Interner,
+    #[cfg(feature = "harfbuzz")]
+    shaper: Option<shaping::Shaper>,
 }
 
 impl NvimState {
@@ -193,44 +244,82 @@ impl NvimState {
             grids: HashMap::new(),
             hl_attrs: HashMap::new(),
             cursor_grid: 0,
-            cmdline_content: String::new(),
-            cmdline_firstc: ' ',
-            cmdline_pos: 0,
-            cmdline_prompt: String::new(),
-            cmdline_shown: false,
+            cmdline: None,
+            cmdline_block: vec![],
             cursor_on: true,
-            message_attrs: vec![],
-            message_contents: vec![],
-            message_time: Instant::now(),
-            has_moved_since_last_message: false,
+            mouse_enabled: false,
+            messages: Messages::new(),
+            showmode: vec![],
+            showcmd: vec![],
+            ruler: vec![],
+            hl_groups: HashMap::new(),
+            popupmenu: None,
+            pending_splits: VecDeque::new(),
+            pending_splits_since: Instant::now(),
+            title: String::new(),
+            buffer_info_outdated: false,
+            cursor_style_enabled: false,
+            mode_infos: vec![],
+            mode_idx: 0,
+            cursor_moved_at: Instant::now(),
+            guifont: String::new(),
+            linespace: 0,
+            font_changed: false,
+            cell_texts: Interner::new(),
+            #[cfg(feature = "harfbuzz")]
+            shaper: None,
         }
     }
+    pub fn cmdline_block_append(&mut self, line: Vec<Chunk>) {
+        let line = self.cell_texts.cells(&line);
+        self.cmdline_block.push(line);
+    }
+    pub fn cmdline_block_hide(&mut self) {
+        self.cmdline_block.clear();
+    }
+    pub fn cmdline_block_show(&mut self, lines: Vec<Vec<Chunk>>) {
+        self.cmdline_block = lines
+            .iter()
+            .map(|line| self.cell_texts.cells(line))
+            .collect();
+    }
     pub fn cmdline_hide(&mut self) {
-        self.cmdline_shown = false;
+        self.cmdline = None;
     }
-    pub fn cmdline_pos(&mut self, pos: u64, _level: u64) {
-        self.cmdline_pos = pos;
+    pub fn cmdline_pos(&mut self, pos: u64, level: u64) {
+        if let Some(cmdline) = self.cmdline.as_mut().filter(|c| c.level == level) {
+            cmdline.pos = pos as usize;
+        }
     }
     pub fn cmdline_show(
         &mut self,
-        content: &Vec<Value>,
+        content: Vec<Chunk>,
         pos: u64,
-        firstc: &str,
-        prompt: &str,
-        _indent: u64,
-        _level: u64,
+        firstc: String,
+        prompt: String,
+        indent: u64,
+        level: u64,
     ) {
-        self.cmdline_content = content.into_iter().fold("".to_string(), |s, v| {
-            s + if let Some(a) = v.as_array() {
-                a[1].as_str().or(Some("")).unwrap()
-            } else {
-                ""
-            }
-        });
-        self.cmdline_firstc = firstc.chars().next().or(Some(' ')).unwrap();
-        self.cmdline_pos = pos;
-        self.cmdline_prompt = prompt.to_string();
-        self.cmdline_shown = true;
+        // Showing the cmdline again means the special character has been replaced by what it
+        // was waiting for
+        self.cmdline = Some(Cmdline::new(
+            &content,
+            pos as usize,
+            &firstc,
+            &prompt,
+            indent as usize,
+            level,
+            &mut self.cell_texts,
+        ));
+    }
+    pub fn cmdline_special_char(&mut self, c: String, shift: bool, level: u64) {
+        if let Some(cmdline) = self.cmdline.as_mut().filter(|c| c.level == level) {
+            let cells = self.cell_texts.cells(&[Chunk {
+                attr_id: 0,
+                text: c,
+            }]);
+            cmdline.special_char = Some((cells, shift));
+        }
     }
     pub fn default_colors_set(
         &mut self,
@@ -261,8 +350,9 @@ impl NvimState {
         if let Some(grid) = self.grids.get_mut(&id) {
             for row in 0..grid.get_height() {
                 for column in 0..grid.get_width() {
-                    grid.chars[row][column] = None;
+                    grid.cells[row][column] = SPACE;
                     grid.colors[row][column] = 0;
+                    grid.clusters[row][column] = None;
                 }
             }
         }
@@ -283,7 +373,8 @@ impl NvimState {
                 width: 1,
                 height: 1,
             });
-            self.has_moved_since_last_message = true;
+            self.messages.cursor_moved();
+            self.cursor_moved_at = Instant::now();
         }
     }
     pub fn grid_resize(&mut self, id: NvimGridId, width: NvimWidth, height: NvimHeight) {
@@ -294,8 +385,9 @@ impl NvimState {
             self.grids.get_mut(&id).unwrap()
         };
         if grid.get_height() > height {
-            grid.chars.truncate(height);
+            grid.cells.truncate(height);
             grid.colors.truncate(height);
+            grid.clusters.truncate(height);
         } else {
             // grid.damages.push(Damage::Cell {
             //     row: grid.get_height(),
@@ -304,8 +396,9 @@ impl NvimState {
             //     height: height - grid.get_height(),
             // });
             for _count in grid.get_height()..height {
-                grid.chars.push(vec![None; width]);
+                grid.cells.push(vec![SPACE; width]);
                 grid.colors.push(vec![0; width]);
+                grid.clusters.push(vec![None; width]);
             }
         }
         if grid.get_width() != width {
@@ -318,8 +411,9 @@ impl NvimState {
             //     });
             // }
             for row in 0..grid.get_height() {
-                grid.chars[row].resize(width as usize, Some(' '));
+                grid.cells[row].resize(width as usize, SPACE);
                 grid.colors[row].resize(width as usize, 0);
+                grid.clusters[row].resize(width as usize, None);
             }
         }
         grid.damages.push(Damage::Cell {
@@ -334,38 +428,126 @@ impl NvimState {
         id: NvimGridId,
         row: NvimRow,
         col_start: NvimColumn,
-        cells: &Vec<Value>,
+        cells: &[GridLineCell],
     ) {
-        let grid = self.grids.get_mut(&id).unwrap();
-        let chars = &mut grid.chars[row as usize];
+        let grid = if let Some(g) = self.grids.get_mut(&id) {
+            g
+        } else {
+            eprintln!("grid_line: unknown grid {}", id);
+            return;
+        };
+        if row >= grid.get_height() {
+            eprintln!("grid_line: row {} out of grid {}", row, id);
+            return;
+        }
+        let width = grid.get_width();
+        let texts = &mut grid.cells[row as usize];
         let colors = &mut grid.colors[row as usize];
         let mut prev_column = col_start as usize;
         let mut prev_color = 0;
-        let mut damage_length: NvimWidth = 0;
         for cell in cells {
-            let mut c = cell.as_array().unwrap().into_iter();
-            let char = c.next().unwrap();
-            if let Some(Value::Integer(color)) = c.next() {
-                prev_color = color.as_u64().unwrap();
+            if let Some(color) = cell.hl_id {
+                prev_color = color;
             }
-            let repeat = (if let Some(Value::Integer(r)) = c.next() {
-                r.as_u64().unwrap()
-            } else {
-                1
-            }) as NvimWidth;
-            for _times in 0..repeat {
-                chars[prev_column] = char.as_str().unwrap().chars().next();
+            let text = self.cell_texts.intern(&cell.text);
+            for _times in 0..cell.repeat {
+                if prev_column >= width {
+                    break;
+                }
+                texts[prev_column] = text;
                 colors[prev_column] = prev_color;
                 prev_column += 1;
             }
-            damage_length += repeat;
         }
         grid.damages.push(Damage::Cell {
             row,
             column: col_start,
-            width: damage_length,
+            width: prev_column.saturating_sub(col_start),
             height: 1,
         });
+        #[cfg(feature = "harfbuzz")]
+        self.shape_line(id, row);
+    }
+    /// Finds the clusters of `row` again. Clusters can change outside of the cells that changed,
+    /// the cells whose cluster changed are redrawn too.
+    #[cfg(feature = "harfbuzz")]
+    fn shape_line(&mut self, id: NvimGridId, row: NvimRow) {
+        if let Some(grid) = self.grids.get_mut(&id) {
+            let previous = grid.clusters[row].clone();
+            match self.shaper.as_mut() {
+                Some(shaper) => {
+                    let hl_attrs = &self.hl_attrs;
+                    let styles: Vec<Style> = grid.colors[row]
+                        .iter()
+                        .map(|attr_id| {
+                            hl_attrs
+                                .get(attr_id)
+                                .map_or(Style::Regular, |hl| Style::new(hl.bold, hl.italic))
+                        })
+                        .collect();
+                    shaper.shape_line(
+                        &grid.cells[row],
+                        &grid.colors[row],
+                        &styles,
+                        &mut grid.clusters[row],
+                        &mut self.cell_texts,
+                    )
+                }
+                None => grid.clusters[row].iter_mut().for_each(|c| *c = None),
+            }
+            let changed = |(column, cluster): (usize, &Option<ClusterSlice>)| {
+                if *cluster != previous[column] {
+                    Some(column)
+                } else {
+                    None
+                }
+            };
+            let first = grid.clusters[row].iter().enumerate().find_map(changed);
+            let last = grid.clusters[row]
+                .iter()
+                .enumerate()
+                .rev()
+                .find_map(changed);
+            if let (Some(first), Some(last)) = (first, last) {
+                grid.damages.push(Damage::Cell {
+                    row,
+                    column: first,
+                    width: last + 1 - first,
+                    height: 1,
+                });
+            }
+        }
+        if self.cell_texts.should_sweep_clusters() {
+            let used = self
+                .grids
+                .values()
+                .flat_map(|grid| grid.clusters.iter().flatten().flatten())
+                .map(|cluster| cluster.0)
+                .collect();
+            self.cell_texts.sweep_clusters(&used);
+        }
+    }
+    /// Shapes text with the primary font of `spec` from now on, e.g. when the font changes. Text
+    /// isn't shaped if the font can't be loaded.
+    #[cfg(feature = "harfbuzz")]
+    pub fn load_shaper(&mut self, spec: &FontSpec) {
+        self.shaper = match shaping::Shaper::load(spec) {
+            Ok(shaper) => Some(shaper),
+            Err(e) => {
+                eprintln!("Can't shape text: {}", e);
+                None
+            }
+        };
+        let rows: Vec<(NvimGridId, NvimHeight)> = self
+            .grids
+            .iter()
+            .map(|(id, grid)| (*id, grid.get_height()))
+            .collect();
+        for (id, height) in rows {
+            for row in 0..height {
+                self.shape_line(id, row);
+            }
+        }
     }
     pub fn grid_scroll(
         &mut self,
@@ -375,147 +557,203 @@ impl NvimState {
         left: NvimColumn,
         right: NvimColumn,
         rows: i64,
-        _cols: i64,
+        cols: i64,
     ) {
-        assert!(_cols == 0);
         if let Some(grid) = self.grids.get_mut(&id) {
-            if rows > 0 {
-                // Moving characters up
-                let r: usize = rows as usize;
-                let bottom = if (bot + r) >= grid.get_height() {
-                    grid.get_height() - r
-                } else {
-                    bot
-                };
-                for y in top..bottom {
-                    for x in left..right {
-                        grid.chars[y][x] = grid.chars[y + r][x];
-                        grid.colors[y][x] = grid.colors[y + r][x];
-                    }
+            let bot = std::cmp::min(bot, grid.get_height());
+            let right = std::cmp::min(right, grid.get_width());
+            let (from_row, to_row, height) = scrolled_span(top, bot, rows);
+            let (from_col, to_col, width) = scrolled_span(left, right, cols);
+            if (rows == 0 && cols == 0) || height == 0 || width == 0 {
+                return;
+            }
+            // Cells are copied in the order that moves them before they get overwritten
+            let row_order: Vec<usize> = if to_row <= from_row {
+                (0..height).collect()
+            } else {
+                (0..height).rev().collect()
+            };
+            let col_order: Vec<usize> = if to_col <= from_col {
+                (0..width).collect()
+            } else {
+                (0..width).rev().collect()
+            };
+            for &y in &row_order {
+                for &x in &col_order {
+                    let (fy, fx, ty, tx) = (from_row + y, from_col + x, to_row + y, to_col + x);
+                    grid.cells[ty][tx] = grid.cells[fy][fx];
+                    grid.colors[ty][tx] = grid.colors[fy][fx];
+                    grid.clusters[ty][tx] = grid.clusters[fy][fx];
                 }
-                grid.damages.push(Damage::VerticalScroll {
-                    from_row: top + r,
-                    to_row: top,
-                    height: bottom - top,
-                    from_col: left,
-                    width: right - left,
-                });
-            } else if rows < 0 {
-                // Moving characters down
-                let mut y = bot - 1;
-                while y >= top && ((y as i64) + rows) >= 0 {
-                    for x in left..right {
-                        grid.chars[y][x] = grid.chars[((y as i64) + rows) as usize][x];
-                        grid.colors[y][x] = grid.colors[((y as i64) + rows) as usize][x];
-                    }
-                    y -= 1
-                }
-                grid.damages.push(Damage::VerticalScroll {
-                    from_row: top,
-                    to_row: top + (rows.abs() as usize),
-                    height: bot - top - (rows.abs() as usize),
-                    from_col: left,
-                    width: right - left,
-                });
             }
+            grid.damages.push(Damage::Scroll {
+                from_row,
+                from_col,
+                to_row,
+                to_col,
+                width,
+                height,
+            });
         }
     }
-    pub fn hl_attr_define(&mut self, id: u64, map: &Vec<(Value, Value)>) {
+    pub fn hl_attr_define(&mut self, id: u64, attrs: &[HlAttr]) {
         let attr = if let Some(a) = self.hl_attrs.get_mut(&id) {
             a
         } else {
             self.hl_attrs.insert(id, NvimHighlightAttribute::new());
             self.hl_attrs.get_mut(&id).unwrap()
         };
-        for (k, v) in map {
-            let key = k.as_str().or(Some("")).unwrap();
-            match key {
-                "foreground" => {
-                    attr.foreground = v.as_u64().map(|c| to_sdl_color(c));
-                }
-                "background" => {
-                    attr.background = v.as_u64().map(|c| to_sdl_color(c));
-                }
-                "special" => {
-                    attr.special = v.as_u64().map(|c| to_sdl_color(c));
-                }
-                "blend" => {
-                    attr.blend = v.as_u64().unwrap() as u8;
-                }
-                "reverse" => {
-                    attr.reverse = v.as_bool().unwrap();
-                }
-                "italic" => {
-                    attr.italic = v.as_bool().unwrap();
-                }
-                "bold" => {
-                    attr.bold = v.as_bool().unwrap();
-                }
-                "strikethrough" => {
-                    attr.strikethrough = v.as_bool().unwrap();
-                }
-                "underline" => {
-                    attr.underline = v.as_bool().unwrap();
-                }
-                "undercurl" => {
-                    attr.undercurl = v.as_bool().unwrap();
-                }
-                "fg_indexed" => {
-                    // Do nothing, only useful for UIs running in terminals
-                }
-                _ => {
-                    println!("Unsupported hl attr key {} in {:?}", key, map);
+        for a in attrs {
+            match a {
+                HlAttr::Foreground(c) => attr.foreground = c.map(to_sdl_color),
+                HlAttr::Background(c) => attr.background = c.map(to_sdl_color),
+                HlAttr::Special(c) => attr.special = c.map(to_sdl_color),
+                HlAttr::Blend(b) => attr.blend = *b,
+                HlAttr::Reverse(b) => attr.reverse = *b,
+                HlAttr::Italic(b) => attr.italic = *b,
+                HlAttr::Bold(b) => attr.bold = *b,
+                HlAttr::Strikethrough(b) => attr.strikethrough = *b,
+                HlAttr::Underline(b) => attr.underline = *b,
+                HlAttr::Undercurl(b) => attr.undercurl = *b,
+                HlAttr::Unsupported(key) => {
+                    eprintln!("Unsupported hl attr key {} in hl_attr_define {}", key, id);
                 }
             }
         }
     }
+    pub fn hl_group_set(&mut self, name: &str, hl_id: u64) {
+        self.hl_groups.insert(name.to_string(), hl_id);
+    }
+    /// The attributes of the builtin highlight group `name`, if neovim told us about it.
+    pub fn hl_group(&self, name: &str) -> Option<&NvimHighlightAttribute> {
+        self.hl_groups
+            .get(name)
+            .and_then(|id| self.hl_attrs.get(id))
+    }
+    pub fn mode_change(&mut self, _mode: &str, mode_idx: usize) {
+        self.mode_idx = mode_idx;
+        self.cursor_moved_at = Instant::now();
+    }
+    pub fn mode_info_set(&mut self, cursor_style_enabled: bool, modes: Vec<ModeInfo>) {
+        self.cursor_style_enabled = cursor_style_enabled;
+        self.mode_infos = modes;
+    }
+    /// The cursor style of the current mode, None if the cursor should look like a plain block.
+    pub fn current_mode(&self) -> Option<&ModeInfo> {
+        if self.cursor_style_enabled {
+            self.mode_infos.get(self.mode_idx)
+        } else {
+            None
+        }
+    }
+    /// Whether a blinking cursor is in its visible phase. Like in vim, the cursor stays visible
+    /// for blinkwait ms after moving, then is hidden for blinkoff ms and shown for blinkon ms.
+    pub fn cursor_blink_visible(&self) -> bool {
+        let mode = match self.current_mode() {
+            Some(mode) if mode.blinkwait > 0 && mode.blinkon > 0 && mode.blinkoff > 0 => mode,
+            _ => return true,
+        };
+        let elapsed = self.cursor_moved_at.elapsed().as_millis() as u64;
+        if elapsed < mode.blinkwait {
+            return true;
+        }
+        (elapsed - mode.blinkwait) % (mode.blinkon + mode.blinkoff) >= mode.blinkoff
+    }
     pub fn msg_clear(&mut self) {
-        self.message_attrs.truncate(0);
-        self.message_contents.truncate(0);
-    }
-    pub fn msg_show(&mut self, _kind: &str, content: &Vec<Value>, _replace_last: bool) {
-        for c in content {
-            if let Some(arr) = c.as_array() {
-                let mut args = arr.into_iter();
-                self.message_attrs
-                    .push(args.next().map_or(0, |v| v.as_u64().or(Some(0)).unwrap()));
-                self.message_contents
-                    .push(args.next().map_or("".to_string(), |v| v.as_str().or(Some("")).unwrap().to_string()));
+        self.messages.clear();
+    }
+    pub fn msg_history_clear(&mut self) {
+        self.messages.history_clear();
+    }
+    pub fn msg_history_show(&mut self, entries: Vec<(String, Vec<Chunk>)>) {
+        let entries = entries
+            .into_iter()
+            .map(|(kind, content)| Message::new(kind, &content, &mut self.cell_texts))
+            .collect();
+        self.messages.history_show(entries);
+    }
+    pub fn msg_ruler(&mut self, content: Vec<Chunk>) {
+        self.ruler = self.cell_texts.cells(&content);
+    }
+    pub fn msg_show(&mut self, kind: String, content: Vec<Chunk>, replace_last: bool) {
+        let message = Message::new(kind, &content, &mut self.cell_texts);
+        self.messages.show(message, replace_last);
+    }
+    pub fn msg_showcmd(&mut self, content: Vec<Chunk>) {
+        self.showcmd = self.cell_texts.cells(&content);
+    }
+    pub fn msg_showmode(&mut self, content: Vec<Chunk>) {
+        self.showmode = self.cell_texts.cells(&content);
+    }
+    pub fn popupmenu_hide(&mut self) {
+        self.popupmenu = None;
+    }
+    pub fn popupmenu_select(&mut self, selected: i64) {
+        if let Some(menu) = self.popupmenu.as_mut() {
+            menu.select(selected);
+        }
+    }
+    pub fn popupmenu_show(
+        &mut self,
+        items: Vec<PopupmenuItem>,
+        selected: i64,
+        row: NvimRow,
+        col: NvimColumn,
+        grid: i64,
+    ) {
+        self.popupmenu = Some(Popupmenu::new(
+            items,
+            selected,
+            row,
+            col,
+            grid,
+            &mut self.cell_texts,
+        ));
+    }
+    /// Shows cmdline completion candidates in the popupmenu, anchored to the start of the word
+    /// being completed.
+    pub fn wildmenu_show(&mut self, items: Vec<String>) {
+        let col = self.cmdline.as_ref().map_or(0, |c| c.word_start());
+        let items = items
+            .into_iter()
+            .map(|word| PopupmenuItem {
+                word,
+                kind: String::new(),
+                menu: String::new(),
+                info: String::new(),
+            })
+            .collect();
+        self.popupmenu = Some(Popupmenu::new(items, -1, 0, col, -1, &mut self.cell_texts));
+    }
+    pub fn option_set(&mut self, option: UiOption) {
+        match option {
+            UiOption::GuiFont(guifont) => {
+                if guifont != self.guifont {
+                    self.guifont = guifont;
+                    self.font_changed = true;
+                }
+            }
+            UiOption::LineSpace(linespace) => {
+                if linespace != self.linespace {
+                    self.linespace = linespace;
+                    self.font_changed = true;
+                }
             }
+            UiOption::Other(_) => {}
         }
-        self.message_time = Instant::now();
-        self.has_moved_since_last_message = false;
-    }
-    pub fn win_hide(&mut self, sway: &mut Connection, win: NvimWinId) {
-        let title = format!("Nwin - Grid {}", win);
-        // Find the parent node of the window being split
-        let parent_node = sway
-            .get_tree()
-            .unwrap()
-            .find(|node| {
-                for n in &node.nodes {
-                    if let Some(str) = &n.name {
-                        if str == &title {
-                            return true;
-                        }
-                    }
+    }
+    pub fn win_hide(&mut self, wm: &mut dyn WindowManager, grid: NvimGridId) {
+        if let Some(container) = wm.find_by_mark(&wm::grid_mark(grid)) {
+            if container.parent_layout != Layout::Tabbed {
+                if let Err(e) = wm
+                    .split(&container, Split::Vertical)
+                    .and_then(|_| wm.set_tabbed(&container))
+                {
+                    eprintln!("win_hide: {}", e);
                 }
-                false
-            })
-            .unwrap();
-        if parent_node.layout != NodeLayout::Tabbed {
-            let node = parent_node
-                .find(|n| {
-                    if let Some(str) = &n.name {
-                        return str == &title;
-                    }
-                    false
-                })
-                .unwrap();
-            sway.run_command(format!("[con_id={}] splitv", node.id))
-                .unwrap();
-            sway.run_command(format!("[con_id={}] layout tabbed", node.id))
-                .unwrap();
+            }
+        } else {
+            eprintln!("win_hide: couldn't find the container of grid {}", grid);
         }
     }
     pub fn win_pos(
@@ -527,474 +765,691 @@ impl NvimState {
         _width: NvimWidth,
         _height: NvimHeight,
     ) {
-        let grid = self.grids.get_mut(&grid).unwrap();
-        grid.window_id = win;
+        if let Some(grid) = self.grids.get_mut(&grid) {
+            if grid.window_id != win {
+                grid.window_id = win;
+                self.buffer_info_outdated = true;
+            }
+        } else {
+            eprintln!("win_pos: unknown grid {}", grid);
+        }
+    }
+    pub fn win_viewport(&mut self, grid: NvimGridId, topline: u64, scroll_delta: Option<i64>) {
+        if let Some(grid) = self.grids.get_mut(&grid) {
+            let delta = scroll_delta.unwrap_or(topline as i64 - grid.topline as i64);
+            grid.viewport_scrolled |= delta != 0;
+            grid.topline = topline;
+        }
     }
     pub fn win_split(
         &mut self,
-        sway: &mut Connection,
+        wm: &mut dyn WindowManager,
         _win1: NvimWinId,
         grid1: NvimGridId,
         _win2: NvimWinId,
         _grid2: NvimGridId,
         flags: SplitDirection,
     ) {
-        let (split_command, desired_sway_layout) = match flags {
-            SplitDirection::Above | SplitDirection::Below => ("splitv", NodeLayout::SplitV),
-            _ => ("splith", NodeLayout::SplitH),
-        };
-        let title = format!("Nwin - Grid {}", grid1);
-        // Find the parent node of the window being split
-        let parent_node = sway
-            .get_tree()
-            .unwrap()
-            .find(|node| {
-                for n in &node.nodes {
-                    if let Some(str) = &n.name {
-                        if str == &title {
-                            return true;
-                        }
+        if !split_grid_container(wm, grid1, flags, false) {
+            eprintln!("win_split: couldn't find the container of grid {}", grid1);
+        }
+    }
+    /// Turns the output of winlayout() into the list of splits that recreate it. The first leaf
+    /// of each node stands for the whole node when splitting it from its siblings, which is why
+    /// splits between siblings come before the splits inside of them.
+    pub fn restore_layout(&mut self, layout: &Value) {
+        fn first_leaf(layout: &Value) -> Option<NvimWinId> {
+            let node = layout.as_array()?;
+            match node.first()?.as_str()? {
+                "leaf" => node.get(1)?.as_u64(),
+                _ => first_leaf(node.get(1)?.as_array()?.first()?),
+            }
+        }
+        fn walk(layout: &Value, splits: &mut VecDeque<PendingSplit>) {
+            let node = if let Some(n) = layout.as_array() {
+                n
+            } else {
+                return;
+            };
+            let direction = match node.first().and_then(|v| v.as_str()) {
+                Some("row") => SplitDirection::Right,
+                Some("col") => SplitDirection::Below,
+                _ => return,
+            };
+            if let Some(children) = node.get(1).and_then(|v| v.as_array()) {
+                for pair in children.windows(2) {
+                    if let (Some(parent), Some(child)) =
+                        (first_leaf(&pair[0]), first_leaf(&pair[1]))
+                    {
+                        splits.push_back(PendingSplit {
+                            parent,
+                            child,
+                            direction,
+                        });
                     }
                 }
-                false
-            })
-            .unwrap();
-        if parent_node.layout != desired_sway_layout {
-            let node = parent_node
-                .find(|n| {
-                    if let Some(str) = &n.name {
-                        return str == &title;
-                    }
-                    false
-                })
-                .unwrap();
-            let command = format!("[con_id={}] {}", node.id, split_command);
-            sway.run_command(command).unwrap();
+                for child in children {
+                    walk(child, splits);
+                }
+            }
         }
+        self.pending_splits.clear();
+        walk(layout, &mut self.pending_splits);
+        self.pending_splits_since = Instant::now();
+    }
+    pub fn grid_of_window(&self, win: NvimWinId) -> Option<NvimGridId> {
+        self.grids
+            .iter()
+            .find(|(_, g)| g.window_id == win)
+            .map(|(id, _)| *id)
+    }
+    pub fn is_pending_split_child(&self, win: NvimWinId) -> bool {
+        self.pending_splits.iter().any(|s| s.child == win)
+    }
+    /// Performs the next split needed to restore the layout given to restore_layout(), once the
+    /// window being split has been mapped and marked. Returns the grid that can now get its OS
+    /// window.
+    pub fn restore_next_split(
+        &mut self,
+        wm: &mut dyn WindowManager,
+        is_marked: impl Fn(NvimGridId) -> bool,
+    ) -> Option<NvimGridId> {
+        let (parent, child, direction) = self
+            .pending_splits
+            .front()
+            .map(|s| (s.parent, s.child, s.direction))?;
+        // Windows might have been closed since we attached, don't wait for them forever.
+        if self.pending_splits_since.elapsed().as_secs() > 5 {
+            eprintln!("Giving up on restoring the window layout.");
+            self.pending_splits.clear();
+            return None;
+        }
+        let parent_grid = self.grid_of_window(parent)?;
+        let child_grid = self.grid_of_window(child)?;
+        if !is_marked(parent_grid) || !split_grid_container(wm, parent_grid, direction, true) {
+            return None;
+        }
+        self.pending_splits.pop_front();
+        let grid = self.grids.get_mut(&child_grid).unwrap();
+        grid.damages.push(Damage::Cell {
+            row: 0,
+            column: 0,
+            width: grid.get_width(),
+            height: grid.get_height(),
+        });
+        Some(child_grid)
     }
 }
 
-fn do_redraw(state: &mut NvimState, sway: &mut Connection, args: Drain<'_, Value>) {
-    for update_events in args {
-        if let Value::Array(update_events) = update_events {
-            let mut update_events_iter = update_events.into_iter();
-            if let Some(event_name) = update_events_iter.next() {
-                if let Some(str) = event_name.as_str() {
-                    for events in update_events_iter {
-                        let arr = events.as_array();
-                        match str {
-                            "busy_start" => {
-                                state.cursor_on = false;
-                            }
-                            "busy_stop" => {
-                                state.cursor_on = true;
-                            }
-                            "cmdline_hide" => {
-                                state.cmdline_hide();
-                            }
-                            "cmdline_pos" => {
-                                let mut args = arr.unwrap().into_iter();
-                                state.cmdline_pos(
-                                    args.next().unwrap().as_u64().unwrap(),
-                                    args.next().unwrap().as_u64().unwrap(),
-                                );
-                            }
-                            "cmdline_show" => {
-                                let mut args = arr.unwrap().into_iter();
-                                state.cmdline_show(
-                                    args.next().unwrap().as_array().unwrap(),
-                                    args.next().unwrap().as_u64().unwrap(),
-                                    args.next().unwrap().as_str().unwrap(),
-                                    args.next().unwrap().as_str().unwrap(),
-                                    args.next().unwrap().as_u64().unwrap(),
-                                    args.next().unwrap().as_u64().unwrap(),
-                                );
-                            }
-                            "default_colors_set" => {
-                                let mut args = arr.unwrap().into_iter();
-                                state.default_colors_set(
-                                    args.next().map(|v| v.as_u64().unwrap()),
-                                    args.next().map(|v| v.as_u64().unwrap()),
-                                    args.next().map(|v| v.as_u64().unwrap()),
-                                );
-                            }
-                            "grid_clear" => {
-                                let mut args = arr.unwrap().into_iter();
-                                state.grid_clear(
-                                    args.next().unwrap().as_u64().unwrap() as NvimGridId
-                                );
-                            }
-                            "grid_destroy" => {
-                                let mut args = arr.unwrap().into_iter();
-                                state.grid_destroy(
-                                    args.next().unwrap().as_u64().unwrap() as NvimGridId
-                                );
-                            }
-                            "grid_cursor_goto" => {
-                                let mut args = arr.unwrap().into_iter();
-                                state.grid_cursor_goto(
-                                    args.next().unwrap().as_u64().unwrap() as NvimGridId,
-                                    args.next().unwrap().as_u64().unwrap() as NvimRow,
-                                    args.next().unwrap().as_u64().unwrap() as NvimColumn,
-                                );
-                            }
-                            "grid_line" => {
-                                let mut args = arr.unwrap().into_iter();
-                                let grid = args.next().unwrap().as_u64().unwrap() as NvimGridId;
-                                let row = args.next().unwrap().as_u64().unwrap() as NvimRow;
-                                let col_start =
-                                    args.next().unwrap().as_u64().unwrap() as NvimColumn;
-                                if let Value::Array(cells) = args.next().unwrap() {
-                                    state.grid_line(grid, row, col_start, &cells);
-                                }
-                            }
-                            "grid_resize" => {
-                                let mut args = arr.unwrap().into_iter();
-                                state.grid_resize(
-                                    args.next().unwrap().as_u64().unwrap() as NvimGridId,
-                                    args.next().unwrap().as_u64().unwrap() as NvimWidth,
-                                    args.next().unwrap().as_u64().unwrap() as NvimHeight,
-                                );
-                            }
-                            "grid_scroll" => {
-                                let mut args = arr.unwrap().into_iter();
-                                state.grid_scroll(
-                                    args.next().unwrap().as_u64().unwrap() as NvimGridId,
-                                    args.next().unwrap().as_u64().unwrap() as NvimRow,
-                                    args.next().unwrap().as_u64().unwrap() as NvimRow,
-                                    args.next().unwrap().as_u64().unwrap() as NvimColumn,
-                                    args.next().unwrap().as_u64().unwrap() as NvimColumn,
-                                    args.next().unwrap().as_i64().unwrap(),
-                                    args.next().unwrap().as_i64().unwrap(),
-                                );
-                            }
-                            "hl_attr_define" => {
-                                let mut args = arr.unwrap().into_iter();
-                                state.hl_attr_define(
-                                    args.next().unwrap().as_u64().unwrap(),
-                                    args.next().unwrap().as_map().unwrap(),
-                                );
-                            }
-                            "msg_clear" => {
-                                state.msg_clear();
-                            }
-                            "msg_show" => {
-                                let mut args = arr.unwrap().into_iter();
-                                state.msg_show(
-                                    args.next().unwrap().as_str().unwrap(),
-                                    args.next().unwrap().as_array().unwrap(),
-                                    args.next().unwrap().as_bool().unwrap(),
-                                )
-                            }
-                            "win_hide" => {
-                                let mut args = arr.unwrap().into_iter();
-                                state.win_hide(
-                                    sway,
-                                    args.next().unwrap().as_u64().unwrap() as NvimWinId,
-                                );
-                            }
-                            "win_pos" => {
-                                let mut args = arr.unwrap().into_iter();
-                                let grid_id = args.next().unwrap().as_u64().unwrap() as NvimGridId;
-                                // neovim-lib doesn't unpack ext types so we end up having to do it
-                                // ourselves. Pretty stupid...
-                                // https://github.com/msgpack/msgpack/blob/master/spec.md#extension-types
-                                // https://github.com/msgpack/msgpack/blob/master/spec.md#int-format-family
-                                let (t, values) = args.next().unwrap().as_ext().unwrap();
-                                // 1 is the id for the window type, 0xCD means 16bit integer
-                                assert!(t == 1 && values[0] == 0xCD && values.len() == 3);
-                                let win_id =
-                                    (values[1] as NvimWinId) << 8 | (values[2] as NvimWinId);
-                                state.win_pos(
-                                    grid_id,
-                                    win_id as NvimWinId,
-                                    args.next().unwrap().as_u64().unwrap() as NvimRow,
-                                    args.next().unwrap().as_u64().unwrap() as NvimColumn,
-                                    args.next().unwrap().as_u64().unwrap() as NvimWidth,
-                                    args.next().unwrap().as_u64().unwrap() as NvimHeight,
-                                );
-                            }
-                            "win_split" => {
-                                let mut args = arr.unwrap().into_iter();
-                                state.win_split(
-                                    sway,
-                                    args.next().unwrap().as_u64().unwrap(),
-                                    args.next().unwrap().as_u64().unwrap(),
-                                    args.next().unwrap().as_u64().unwrap(),
-                                    args.next().unwrap().as_u64().unwrap(),
-                                    args.next().unwrap().as_u64().unwrap().try_into().unwrap(),
-                                );
-                            }
-                            "flush" | "hl_group_set" | "mode_info_set" | "mode_change"
-                            | "mouse_off" | "option_set" | "win_viewport" | "msg_showcmd"
-                            | "msg_showmode" => {}
-                            _ => {
-                                println!("Unhandled {}, {:?}", str, events);
-                            }
-                        }
+/// Makes the next window the window manager creates appear next to the container of `grid`, in
+/// `direction`. Returns false if the container couldn't be found, e.g. because the window manager
+/// hasn't mapped it yet.
+fn split_grid_container(
+    wm: &mut dyn WindowManager,
+    grid: NvimGridId,
+    direction: SplitDirection,
+    focus: bool,
+) -> bool {
+    let (split, desired_layout) = match direction {
+        SplitDirection::Above | SplitDirection::Below => (Split::Vertical, Layout::SplitV),
+        _ => (Split::Horizontal, Layout::SplitH),
+    };
+    let container = if let Some(c) = wm.find_by_mark(&wm::grid_mark(grid)) {
+        c
+    } else {
+        return false;
+    };
+    let mut result = Ok(());
+    if focus {
+        result = wm.focus(&container);
+    }
+    if result.is_ok() && container.parent_layout != desired_layout {
+        result = wm.split(&container, split);
+    }
+    if let Err(e) = &result {
+        eprintln!("Failed to split grid {}: {}", grid, e);
+    }
+    result.is_ok()
+}
+
+fn do_redraw(
+    state: &mut NvimState,
+    mut wm: Option<&mut dyn WindowManager>,
+    args: Drain<'_, Value>,
+) {
+    for batch in args {
+        for event in redraw::decode_batch(&batch) {
+            let event = match event {
+                Ok(e) => e,
+                Err(e) => {
+                    eprintln!("Skipping redraw event: {}", e);
+                    continue;
+                }
+            };
+            match event {
+                RedrawEvent::BusyStart => {
+                    state.cursor_on = false;
+                }
+                RedrawEvent::BusyStop => {
+                    state.cursor_on = true;
+                }
+                RedrawEvent::CmdlineBlockAppend { line } => {
+                    state.cmdline_block_append(line);
+                }
+                RedrawEvent::CmdlineBlockHide => {
+                    state.cmdline_block_hide();
+                }
+                RedrawEvent::CmdlineBlockShow { lines } => {
+                    state.cmdline_block_show(lines);
+                }
+                RedrawEvent::CmdlineHide => {
+                    state.cmdline_hide();
+                }
+                RedrawEvent::CmdlinePos { pos, level } => {
+                    state.cmdline_pos(pos, level);
+                }
+                RedrawEvent::CmdlineShow {
+                    content,
+                    pos,
+                    firstc,
+                    prompt,
+                    indent,
+                    level,
+                } => {
+                    state.cmdline_show(content, pos, firstc, prompt, indent, level);
+                }
+                RedrawEvent::CmdlineSpecialChar { c, shift, level } => {
+                    state.cmdline_special_char(c, shift, level);
+                }
+                RedrawEvent::DefaultColorsSet {
+                    rgb_fg,
+                    rgb_bg,
+                    rgb_sp,
+                } => {
+                    state.default_colors_set(rgb_fg, rgb_bg, rgb_sp);
+                }
+                RedrawEvent::GridClear { grid } => {
+                    state.grid_clear(grid);
+                }
+                RedrawEvent::GridDestroy { grid } => {
+                    state.grid_destroy(grid);
+                }
+                RedrawEvent::GridCursorGoto { grid, row, column } => {
+                    state.grid_cursor_goto(grid, row, column);
+                }
+                RedrawEvent::GridLine {
+                    grid,
+                    row,
+                    col_start,
+                    cells,
+                } => {
+                    state.grid_line(grid, row, col_start, &cells);
+                }
+                RedrawEvent::GridResize {
+                    grid,
+                    width,
+                    height,
+                } => {
+                    state.grid_resize(grid, width, height);
+                }
+                RedrawEvent::GridScroll {
+                    grid,
+                    top,
+                    bot,
+                    left,
+                    right,
+                    rows,
+                    cols,
+                } => {
+                    state.grid_scroll(grid, top, bot, left, right, rows, cols);
+                }
+                RedrawEvent::HlAttrDefine { id, attrs } => {
+                    state.hl_attr_define(id, &attrs);
+                }
+                RedrawEvent::HlGroupSet { name, hl_id } => {
+                    state.hl_group_set(&name, hl_id);
+                }
+                RedrawEvent::ModeChange { mode, mode_idx } => {
+                    state.mode_change(&mode, mode_idx);
+                }
+                RedrawEvent::ModeInfoSet {
+                    cursor_style_enabled,
+                    modes,
+                } => {
+                    state.mode_info_set(cursor_style_enabled, modes);
+                }
+                RedrawEvent::MouseOff => {
+                    state.mouse_enabled = false;
+                }
+                RedrawEvent::MouseOn => {
+                    state.mouse_enabled = true;
+                }
+                RedrawEvent::MsgClear => {
+                    state.msg_clear();
+                }
+                RedrawEvent::MsgHistoryClear => {
+                    state.msg_history_clear();
+                }
+                RedrawEvent::MsgHistoryShow { entries } => {
+                    state.msg_history_show(entries);
+                }
+                RedrawEvent::MsgRuler { content } => {
+                    state.msg_ruler(content);
+                }
+                RedrawEvent::MsgShow {
+                    kind,
+                    content,
+                    replace_last,
+                } => {
+                    state.msg_show(kind, content, replace_last);
+                }
+                RedrawEvent::MsgShowcmd { content } => {
+                    state.msg_showcmd(content);
+                }
+                RedrawEvent::MsgShowmode { content } => {
+                    state.msg_showmode(content);
+                }
+                RedrawEvent::OptionSet(option) => {
+                    state.option_set(option);
+                }
+                RedrawEvent::PopupmenuHide => {
+                    state.popupmenu_hide();
+                }
+                RedrawEvent::PopupmenuSelect { selected } => {
+                    state.popupmenu_select(selected);
+                }
+                RedrawEvent::PopupmenuShow {
+                    items,
+                    selected,
+                    row,
+                    col,
+                    grid,
+                } => {
+                    state.popupmenu_show(items, selected, row, col, grid);
+                }
+                RedrawEvent::SetTitle { title } => {
+                    state.title = title;
+                }
+                RedrawEvent::WinHide { grid } => {
+                    if let Some(wm) = wm.as_deref_mut() {
+                        state.win_hide(wm, grid);
                     }
-                } else {
-                    eprintln!("Found non-str event name!");
                 }
-            } else {
-                eprintln!("No event name!");
+                RedrawEvent::WinPos {
+                    grid,
+                    win,
+                    start_row,
+                    start_col,
+                    width,
+                    height,
+                } => {
+                    state.win_pos(grid, win, start_row, start_col, width, height);
+                }
+                RedrawEvent::WinViewport {
+                    grid,
+                    topline,
+                    scroll_delta,
+                } => {
+                    state.win_viewport(grid, topline, scroll_delta);
+                }
+                RedrawEvent::WildmenuHide => {
+                    state.popupmenu_hide();
+                }
+                RedrawEvent::WildmenuSelect { selected } => {
+                    state.popupmenu_select(selected);
+                }
+                RedrawEvent::WildmenuShow { items } => {
+                    state.wildmenu_show(items);
+                }
+                RedrawEvent::WinSplit {
+                    win1,
+                    grid1,
+                    win2,
+                    grid2,
+                    flags,
+                } => {
+                    if let Some(wm) = wm.as_deref_mut() {
+                        state.win_split(wm, win1, grid1, win2, grid2, flags);
+                    }
+                }
+                RedrawEvent::Flush | RedrawEvent::Ignored => {}
             }
-        } else {
-            eprintln!("Unsupported event type {:?}", update_events);
         }
     }
 }
 
-struct SDLGrid {
-    canvas: Canvas<Window>,
-    atlas: Texture,
-    atlas_index: HashMap<AtlasIndexKey, (i32, u32)>,
-    atlas_next_slot: i32,
-    big_texture: Texture,
-    big_texture_copy: Texture,
-    texture_creator: TextureCreator<WindowContext>,
-    width: u32,  // pixels
-    height: u32, // pixels
-    grid_x_offset: u32,
-    grid_y_offset: u32,
-    font_width: u32,
-    font_height: u32,
+/// Addresses containing a colon and that aren't an existing file are TCP `host:port` addresses,
+/// everything else is a unix socket path.
+fn connect_to_server(address: &str) -> std::io::Result<Session> {
+    if address.contains(':') && !Path::new(address).exists() {
+        Session::new_tcp(address)
+    } else {
+        Session::new_unix_socket(address)
+    }
 }
 
-fn find_sdl_gl_driver() -> Option<u32> {
-    for (index, item) in sdl2::render::drivers().enumerate() {
-        if item.name == "opengl" {
-            return Some(index as u32);
+/// Detaches the UI, leaving neovim running.
+fn detach(nvim: &mut Neovim, chan_id: u64) {
+    let commands = [
+        format!("autocmd! nwin_{}", chan_id),
+        format!("augroup! nwin_{}", chan_id),
+        "silent! delcommand NwinDetach".to_string(),
+    ];
+    for command in &commands {
+        if let Err(e) = nvim.command(command) {
+            eprintln!("{} failed: {}", command, e);
         }
     }
-    None
+    if let Err(e) = nvim.ui_detach() {
+        eprintln!("nvim_ui_detach() failed: {}", e);
+    }
 }
 
-static MAX_TEXTURE_SIZE : u32 = 16384;
+/// The grid shown in the OS window `window_id` and the cell under the pixel at `x`, `y` of it.
+fn grid_cell_at(
+    sdl_grids: &HashMap<NvimGridId, SDLGrid>,
+    window_id: u32,
+    x: i32,
+    y: i32,
+) -> Option<(NvimGridId, NvimRow, NvimColumn)> {
+    sdl_grids
+        .iter()
+        .find(|(_, g)| g.canvas.window().id() == window_id)
+        .map(|(id, g)| {
+            let (row, column) = g.cell_at(x, y);
+            (*id, row, column)
+        })
+}
 
-impl SDLGrid {
-    pub fn new(
-        video_subsystem: &VideoSubsystem,
-        id: NvimGridId,
-        font_width: u32,
-        font_height: u32,
-    ) -> SDLGrid {
-        let title = format!("Nwin - Grid {}", id);
-        let width = 1;
-        let height = 1;
-        let window = video_subsystem
-            .window(&title, width, height)
-            .opengl()
-            .resizable()
-            .build()
-            .unwrap();
-        let canvas = window
-            .into_canvas()
-            .index(find_sdl_gl_driver().unwrap())
-            .build()
-            .unwrap();
-        let texture_creator = canvas.texture_creator();
-        let big_texture = texture_creator
-            .create_texture_target(PixelFormatEnum::ARGB8888, width, height)
-            .unwrap();
-        let big_texture_copy = texture_creator
-            .create_texture_target(PixelFormatEnum::ARGB8888, width, height)
-            .unwrap();
-        let mut atlas = texture_creator
-            .create_texture_target(PixelFormatEnum::ARGB8888, MAX_TEXTURE_SIZE, font_height)
-            .unwrap();
-        atlas.set_blend_mode(BlendMode::Blend);
-        SDLGrid {
-            canvas,
-            atlas,
-            atlas_index: HashMap::new(),
-            atlas_next_slot: 0,
-            big_texture,
-            big_texture_copy,
-            texture_creator,
-            width,
-            height,
-            grid_x_offset: 0,
-            grid_y_offset: 0,
-            font_width,
-            font_height,
-        }
+fn input_mouse(
+    nvim: &mut Neovim,
+    button: &str,
+    action: &str,
+    modifier: &str,
+    (grid, row, column): (NvimGridId, NvimRow, NvimColumn),
+) {
+    if let Err(e) = nvim.call_function(
+        "nvim_input_mouse",
+        vec![
+            button.into(),
+            action.into(),
+            modifier.into(),
+            grid.into(),
+            (row as u64).into(),
+            (column as u64).into(),
+        ],
+    ) {
+        eprintln!("nvim_input_mouse({}, {}) failed: {}", button, action, e);
     }
 }
 
-const WHITE : Color = Color::RGBA(255,255,255,255);
-const TRANSPARENT : Color = Color::RGBA(200,0,128,0);
-
 const REF: &str = include_str!("../.git/HEAD");
 const REF_MASTER: &str = include_str!("../.git/refs/heads/master");
 
+/// Size of the UI neovim starts with, in cells. Without a tiling window manager to size them,
+/// windows open at this size too.
+const DEFAULT_COLUMNS: usize = 80;
+const DEFAULT_ROWS: usize = 20;
+
 pub fn main() -> Result<(), String> {
     env::remove_var("NVIM_LISTEN_ADDRESS");
 
-    let mut sway = Connection::new().unwrap();
-
     // Create the command used to run neovim. We swallow the arguments we understand and forward
     // the rest to neovim.
     let mut neovim_command = Command::new("nvim");
     neovim_command.args(&["--embed", "--cmd", "let g:started_by_nwin = v:true"]);
     let mut print_fps = false;
     let mut max_fps = 60;
+    let mut renderer = None;
+    let mut headless_dir = None;
+    let mut record_path = None;
+    let mut replay_path = None;
+    let mut server_address = None;
+    let mut has_neovim_arguments = false;
+    let mut detach_key = "<C-S-d>".to_string();
+    let config = Config::load();
+    let mut font = config.font;
+    let mut smooth_scroll = Duration::from_millis(config.smooth_scroll.unwrap_or(0));
+    let mut messages_window = config.messages_window.unwrap_or(false);
+    let mut status_row = config.status_row.unwrap_or(true);
+    let mut title_template = title::DEFAULT_TEMPLATE.to_string();
     for argument in env::args().skip(1) {
         if argument == "--print-fps" {
             print_fps = true;
         } else if argument.starts_with("--max-fps=") {
             max_fps = argument.get(10..).unwrap().parse::<i64>().unwrap();
+        } else if argument.starts_with("--renderer=") {
+            renderer = Some(argument.get(11..).unwrap().to_string());
+        } else if argument.starts_with("--headless=") {
+            headless_dir = Some(PathBuf::from(argument.get(11..).unwrap()));
+        } else if argument.starts_with("--record=") {
+            record_path = Some(PathBuf::from(argument.get(9..).unwrap()));
+        } else if argument.starts_with("--replay=") {
+            replay_path = Some(PathBuf::from(argument.get(9..).unwrap()));
+        } else if argument.starts_with("--server=") {
+            server_address = Some(argument.get(9..).unwrap().to_string());
+        } else if argument.starts_with("--font=") {
+            font = Some(argument.get(7..).unwrap().to_string());
+        } else if argument.starts_with("--smooth-scroll=") {
+            let value = argument.get(16..).unwrap();
+            let ms = value.parse::<u64>().map_err(|_| {
+                format!("Invalid --smooth-scroll '{}', expected milliseconds", value)
+            })?;
+            smooth_scroll = Duration::from_millis(ms);
+        } else if argument == "--messages-window" {
+            messages_window = true;
+        } else if argument == "--no-status-row" {
+            status_row = false;
+        } else if argument.starts_with("--detach-key=") {
+            detach_key = argument.get(13..).unwrap().to_string();
+        } else if argument.starts_with("--title=") {
+            title_template = argument.get(8..).unwrap().to_string();
         } else {
             neovim_command.arg(argument);
+            has_neovim_arguments = true;
         }
     }
 
-    // Create the neovim session
-    let session = Session::new_child_cmd(&mut neovim_command).unwrap();
-    let mut nvim = Neovim::new(session);
-    let mut state = NvimState::new();
-    let chan = nvim.session.start_event_loop_channel();
+    // Headless mode renders grids to offscreen surfaces, it doesn't need a window manager or a
+    // display. Without a supported window manager, every grid still gets its own OS window, they
+    // just aren't tiled like neovim's windows.
+    let mut wm = if headless_dir.is_some() {
+        None
+    } else {
+        match wm::connect() {
+            Ok(wm) => Some(wm),
+            Err(e) => {
+                eprintln!("Warning: {}. Windows won't be tiled automatically.", e);
+                None
+            }
+        }
+    };
 
-    let commit = if REF.starts_with("ref: refs/heads/master") {
-        REF_MASTER
+    // When replaying a recording, notifications come from the recording instead of neovim.
+    let (mut nvim, chan, has_ext_windows, chan_id) = if let Some(path) = &replay_path {
+        let (header, chan) = recording::replay(path)?;
+        (None, chan, header.has_ext_windows, 0)
     } else {
-        REF
-    }.trim();
-    // Advertise UI name
-    nvim.set_client_info(
-        "nwin",
-        vec![
-            ("major".into(), "0".into()),
-            ("minor".into(), "1".into()),
-            ("patch".into(), "0".into()),
-            ("commit".into(), commit.into()),
-        ],
-        "ui",
-        vec![],
-        vec![],
-    )
-    .unwrap();
+        // Create the neovim session, either by connecting to an existing server or by spawning
+        // our own
+        let session = if let Some(address) = &server_address {
+            if has_neovim_arguments {
+                eprintln!("Warning: arguments are not forwarded to neovim when using --server.");
+            }
+            connect_to_server(address)
+                .map_err(|e| format!("Can't connect to neovim server {}: {}", address, e))?
+        } else {
+            Session::new_child_cmd(&mut neovim_command).unwrap()
+        };
+        let mut nvim = Neovim::new(session);
+        let chan = nvim.session.start_event_loop_channel();
+
+        let commit = if REF.starts_with("ref: refs/heads/master") {
+            REF_MASTER
+        } else {
+            REF
+        }
+        .trim();
+        // Advertise UI name
+        nvim.set_client_info(
+            "nwin",
+            vec![
+                ("major".into(), "0".into()),
+                ("minor".into(), "1".into()),
+                ("patch".into(), "0".into()),
+                ("commit".into(), commit.into()),
+            ],
+            "ui",
+            vec![],
+            vec![],
+        )
+        .unwrap();
 
-    // Two things: retrieve neovim channel and figure out if server supports ext_win
-    let chan_id;
-    let mut has_ext_windows = false;
-    if let Ok(info) = nvim.get_api_info() {
-        chan_id = info[0].as_u64().unwrap();
-        for (key, value) in info[1].as_map().unwrap() {
-            if key.as_str().unwrap() == "ui_options" {
-                for option in value.as_array().unwrap() {
-                    if option.as_str().unwrap() == "ext_windows" {
-                        has_ext_windows = true;
+        // Two things: retrieve neovim channel and figure out if server supports ext_win
+        let chan_id;
+        let mut has_ext_windows = false;
+        if let Ok(info) = nvim.get_api_info() {
+            chan_id = info[0].as_u64().unwrap();
+            for (key, value) in info[1].as_map().unwrap() {
+                if key.as_str().unwrap() == "ui_options" {
+                    for option in value.as_array().unwrap() {
+                        if option.as_str().unwrap() == "ext_windows" {
+                            has_ext_windows = true;
+                        }
                     }
                 }
             }
+        } else {
+            panic!("nvim_get_api_info() failed!");
         }
-    } else {
-        panic!("nvim_get_api_info() failed!");
-    }
 
-    // Use channel id to get warned when server closes.
-    let command = format!(
-        "autocmd VimLeave * call rpcnotify({}, 'nwin_vimleave')",
-        chan_id
-    );
-    nvim.command(&command).unwrap();
+        // Use channel id to get warned when server closes. The autocmd lives in its own group so
+        // that it can be removed when detaching from a server that keeps running.
+        let command = format!("augroup nwin_{} | autocmd! | augroup END", chan_id);
+        nvim.command(&command).unwrap();
+        let command = format!(
+            "autocmd nwin_{} VimLeave * call rpcnotify({}, 'nwin_vimleave')",
+            chan_id, chan_id
+        );
+        nvim.command(&command).unwrap();
+        // Window titles show the name and modified flag of buffers. BufModifiedSet only exists
+        // since neovim 0.5, older servers notify every change made outside of insert mode instead.
+        let modified_events = match nvim.eval("exists('##BufModifiedSet')") {
+            Ok(v) if v.as_u64() == Some(1) => "BufModifiedSet",
+            _ => "BufWritePost,TextChanged,InsertLeave",
+        };
+        let command = format!(
+            "autocmd nwin_{} BufEnter,BufFilePost,{} * call rpcnotify({}, 'nwin_buffer_changed')",
+            chan_id, modified_events, chan_id
+        );
+        nvim.command(&command).unwrap();
+        if server_address.is_some() {
+            let command = format!(
+                "command! NwinDetach call rpcnotify({}, 'nwin_detach')",
+                chan_id
+            );
+            nvim.command(&command).unwrap();
+        }
+
+        (Some(nvim), chan, has_ext_windows, chan_id)
+    };
+    let mut recorder = match &record_path {
+        Some(path) => Some(Recorder::new(path, &RecordingHeader { has_ext_windows })?),
+        None => None,
+    };
 
+    // SDL only reads the SDL_VIDEODRIVER hint since 2.0.22, the environment variable works with
+    // every version as long as it's set before SDL is initialized
+    if headless_dir.is_some() {
+        env::set_var("SDL_VIDEODRIVER", "dummy");
+    }
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
     let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
+    let mut render_driver = RenderDriver::find(renderer.as_deref())?;
 
-    // use home crate to figure out path to ~/downloads/NotoSansMono/NotoSansMono-Regular.ttf
-    let mut _fontpath = String::new();
-    match home::home_dir() {
-        // this might not be a good way..
-        Some(path) => _fontpath.push_str(&path.to_string_lossy()),
-        None => println!("can't find font to use, check README.md"),
-    }
-    _fontpath.push_str("/downloads/NotoSansMono/NotoSansMono-Regular.ttf");
+    // Neovim's 'guifont', once set, replaces this font
+    let default_font = FontSpec::parse(font.as_deref().unwrap_or(fonts::DEFAULT_FONT))?;
+    let mut fonts = Fonts::from_spec(&ttf_context, &default_font)?;
+    let (mut font_width, mut font_height) = fonts.cell_size(0)?;
 
-    let font = ttf_context.load_font(_fontpath.to_string(), 16)?;
-
-    let mut font_width = 1;
-    let mut font_height = 1;
+    // Tiling window managers size OS windows themselves, otherwise windows are created big enough
+    // for the grid they show
+    let tiled = wm.is_some();
 
+    let mut state = NvimState::new();
+    // Headless mode only renders grids
+    let mut messages_window = if messages_window && headless_dir.is_none() {
+        state.messages.windowed = true;
+        Some(MessagesWindow::new(
+            &video_subsystem,
+            &mut render_driver,
+            (!tiled).then_some((DEFAULT_COLUMNS, DEFAULT_ROWS / 2)),
+            (font_width, font_height),
+        ))
+    } else {
+        None
+    };
+    #[cfg(feature = "harfbuzz")]
+    state.load_shaper(&default_font);
     let mut sdl_grids: HashMap<NvimGridId, SDLGrid> = HashMap::new();
+    // Grids whose OS window has been tagged with wm::grid_mark()
+    let mut marked_grids: HashSet<NvimGridId> = HashSet::new();
+    let mut headless = headless_dir
+        .as_ref()
+        .map(|_| HeadlessRenderer::new(font_width, font_height));
 
-    // We need to know the size of the first window in order to be able to attach the neovim GUI
-    // So we cheat and create an SDLGrid for grid id 2 which we know will be the first "buffer"
-    // grid id neovim creates when ext_multigrid is present.
-    // We then use this SDLGrid to compute the different sizes we need and then attach
-    {
+    // Create the first window before attaching so that it shows up as soon as possible. We know
+    // grid id 2 will be the first "buffer" grid id neovim creates when ext_multigrid is present.
+    // This isn't true of servers we attach to, they might already have closed that window.
+    if headless.is_none() && server_address.is_none() {
         let grid_id = if has_ext_windows { 2 } else { 1 };
         sdl_grids.insert(
             grid_id,
             SDLGrid::new(
                 &video_subsystem,
+                &mut render_driver,
                 grid_id,
+                (!tiled).then_some((DEFAULT_COLUMNS, DEFAULT_ROWS)),
+                status_row,
                 font_width,
                 font_height,
             ),
         );
-        let the_grid = sdl_grids.get_mut(&grid_id).unwrap();
-
-        let surface = font
-            .render("A")
-            .blended(Color::RGBA(255, 0, 0, 255))
-            .map_err(|e| e.to_string())?;
-        let texture = the_grid
-            .texture_creator
-            .create_texture_from_surface(&surface)
-            .map_err(|e| e.to_string())?;
-        let t = texture.query();
-        font_width = t.width;
-        font_height = t.height;
-        the_grid.font_width = font_width;
-        the_grid.font_height = font_height;
-
-        let size = the_grid.canvas.window().size();
-        the_grid.width = size.0;
-        the_grid.height = size.0;
+    }
 
-        let row_count = the_grid.width / the_grid.font_width;
-        let col_count = the_grid.height / the_grid.font_height;
+    if let Some(nvim) = nvim.as_mut() {
         let mut options = UiAttachOptions::new();
         options.set_rgb(true);
         options.set_linegrid_external(true);
+        options.set_popupmenu_external(true);
+        // Older servers, and newer ones without "pum" in 'wildoptions', complete cmdlines with
+        // the wildmenu instead of the popupmenu
+        options.set_wildmenu_external(true);
         if has_ext_windows {
             options
                 .set_messages_external(true)
                 .set_multigrid(true)
                 .set_windows_external(true);
         } else {
-            println!(
+            eprintln!(
                 "Warning: neovim server does not support external windows. Continuing without."
             );
         }
-        nvim.ui_attach(80, 20, &options).unwrap();
-
-        the_grid.grid_x_offset = (the_grid.width - (col_count * the_grid.font_width)) / 2;
-        the_grid.grid_y_offset = (the_grid.height - (row_count * the_grid.font_height)) / 2;
-
-        the_grid.big_texture = the_grid
-            .texture_creator
-            .create_texture_target(PixelFormatEnum::ARGB8888, the_grid.width, the_grid.height)
-            .unwrap();
-        the_grid.big_texture_copy = the_grid
-            .texture_creator
-            .create_texture_target(PixelFormatEnum::ARGB8888, the_grid.width, the_grid.height)
+        nvim.ui_attach(DEFAULT_COLUMNS as i64, DEFAULT_ROWS as i64, &options)
             .unwrap();
-        the_grid.atlas = the_grid
-            .texture_creator
-            .create_texture_target(PixelFormatEnum::ARGB8888, MAX_TEXTURE_SIZE, the_grid.font_height)
-            .unwrap();
-        the_grid.atlas.set_blend_mode(BlendMode::Blend);
+        // Servers might already have several windows, try to lay them out the same way.
+        if server_address.is_some() && has_ext_windows && wm.is_some() {
+            match nvim.call_function("winlayout", vec![]) {
+                Ok(layout) => state.restore_layout(&layout),
+                Err(e) => eprintln!("winlayout() failed: {}", e),
+            }
+        }
     }
 
     let mut event_pump = sdl_context.event_pump().map_err(|e| e.to_string())?;
 
-    let mut cursor_rect = Rect::new(0, 0, 0, 0);
     let mut redraw_messages = VecDeque::new();
-    let mut last_frame_check = Instant::now();
     let mut last_second = Instant::now();
     let mut frame_count = 0;
     let mut grids_to_destroy = vec![];
@@ -1002,13 +1457,31 @@ pub fn main() -> Result<(), String> {
     // Note: this can't be inside of loop because we might sometimes draw a frame between two
     // events that have the same timestamp.
     let mut last_keydown_timestamp = 0;
+    // The button being held, and the last cell it was held over, to send drag events
+    let mut pressed_button: Option<&str> = None;
+    let mut pressed_cell = (0, 0, 0);
 
     'running: loop {
         grids_to_destroy.truncate(0);
         let now = Instant::now();
         // 1) Process events from neovim
-        while let Ok((str, messages)) = chan.try_recv() {
+        loop {
+            let (str, messages) = match chan.try_recv() {
+                Ok(notification) => notification,
+                Err(TryRecvError::Empty) => break,
+                // The replay thread stops once it is done, but losing neovim is fatal.
+                Err(TryRecvError::Disconnected) => {
+                    if nvim.is_some() {
+                        eprintln!("Lost connection to neovim.");
+                        break 'running;
+                    }
+                    break;
+                }
+            };
             if str == "redraw" {
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.record(&str, &messages);
+                }
                 // Copy messages into the vecdequeue, remember position of last flush if there's
                 // one.
                 let len = messages.len();
@@ -1030,387 +1503,252 @@ pub fn main() -> Result<(), String> {
                 if let Some(pos) = last_flush_position {
                     do_redraw(
                         &mut state,
-                        &mut sway,
+                        wm.as_mut().map(|wm| wm.as_mut() as &mut dyn WindowManager),
                         redraw_messages.drain(0..redraw_messages.len() - pos),
                     );
                 }
+            } else if str == "nwin_buffer_changed" {
+                state.buffer_info_outdated = true;
             } else if str == "nwin_vimleave" {
                 break 'running;
+            } else if str == "nwin_detach" {
+                if let Some(nvim) = nvim.as_mut() {
+                    detach(nvim, chan_id);
+                }
+                break 'running;
+            } else if str == recording::REPLAY_DONE {
+                // Keep windows open so that the user can look at the result, unless there are
+                // none.
+                if headless.is_some() {
+                    break 'running;
+                }
             } else {
                 eprintln!("Unexpected message: {}", str);
             }
         }
 
-        if last_frame_check.elapsed().as_secs() >= 60 {
-            eprintln!("No frame for more than a minute. Resetting atlas.");
-            for (key, grid) in state.grids.iter_mut() {
-                let s = sdl_grids.get_mut(key).unwrap();
-                s.atlas_index.clear();
-                s.atlas_next_slot = 0;
-                grid.damages.push(
-                    Damage::Cell {
-                        row: 0,
-                        column: 0,
-                        width: grid.get_width(),
-                        height: grid.get_height(),
+        // Buffer names and modified flags aren't part of the UI protocol, ask for them when they
+        // might have changed.
+        if state.buffer_info_outdated {
+            if let Some(nvim) = nvim.as_mut() {
+                title::refresh_buffer_info(nvim, &mut state);
+            }
+            state.buffer_info_outdated = false;
+        }
+
+        // 'guifont' or 'linespace' changed: glyphs have to be rendered again and grids have to be
+        // fit in their windows again.
+        if state.font_changed {
+            state.font_changed = false;
+            let spec = if state.guifont.is_empty() {
+                Ok(default_font.clone())
+            } else {
+                FontSpec::parse(&state.guifont)
+            };
+            match spec
+                .clone()
+                .and_then(|spec| Fonts::from_spec(&ttf_context, &spec))
+            {
+                Ok(new_fonts) => {
+                    fonts = new_fonts;
+                    #[cfg(feature = "harfbuzz")]
+                    state.load_shaper(spec.as_ref().unwrap());
+                }
+                Err(e) => eprintln!("Failed to change font: {}", e),
+            }
+            match fonts.cell_size(state.linespace) {
+                Ok((w, h)) => {
+                    font_width = w;
+                    font_height = h;
+                }
+                Err(e) => eprintln!("Failed to compute cell size: {}", e),
+            }
+            let default_bg = state
+                .hl_attrs
+                .get(&0)
+                .and_then(|hl| hl.background)
+                .unwrap_or(Color::BLACK);
+            for (key, sdl_grid) in sdl_grids.iter_mut() {
+                sdl_grid.set_font_size(font_width, font_height);
+                let size = sdl_grid.canvas.window().size();
+                let (col_count, row_count) =
+                    sdl_grid.resize(size.0, size.1, default_bg, status_row);
+                if let Some(nvim) = nvim.as_mut() {
+                    if let Err(e) = nvim.ui_try_resize_grid(
+                        i64::try_from(*key).unwrap(),
+                        col_count.into(),
+                        row_count.into(),
+                    ) {
+                        eprintln!("{}", e);
+                    }
+                }
+            }
+            if let Some(headless) = headless.as_mut() {
+                headless.set_font_size(font_width, font_height);
+            }
+            for grid in state.grids.values_mut() {
+                grid.damages.push(Damage::Cell {
+                    row: 0,
+                    column: 0,
+                    width: grid.get_width(),
+                    height: grid.get_height(),
+                });
+            }
+        }
+
+        // 2) Mark the windows the window manager mapped since the last frame, so that they can
+        // be found again even if their title changes or other nwin instances are running. Then,
+        // recreate the layout of windows that existed before we attached, one split per frame so
+        // that the window manager has had time to map the window being split
+        if let Some(wm) = wm.as_deref_mut() {
+            for id in sdl_grids.keys() {
+                if marked_grids.contains(id) {
+                    continue;
+                }
+                match wm.mark_window(process::id(), &window_title(*id), &wm::grid_mark(*id)) {
+                    Ok(true) => {
+                        marked_grids.insert(*id);
+                    }
+                    Ok(false) => {}
+                    Err(e) => {
+                        eprintln!("Failed to mark the window of grid {}: {}", id, e);
+                        marked_grids.insert(*id);
                     }
+                }
+            }
+            if let Some(grid_id) = state.restore_next_split(wm, |id| marked_grids.contains(&id)) {
+                sdl_grids.insert(
+                    grid_id,
+                    SDLGrid::new(
+                        &video_subsystem,
+                        &mut render_driver,
+                        grid_id,
+                        None,
+                        status_row,
+                        font_width,
+                        font_height,
+                    ),
                 );
             }
         }
-        last_frame_check = Instant::now();
 
         // 3) Redraw grid damages
         if let Some(default_hl) = state.hl_attrs.get(&0) {
             let default_bg = default_hl.background;
-            let default_fg = default_hl.foreground;
-            for (key, grid) in state.grids.iter_mut() {
-                if has_ext_windows && *key == 1 {
-                    grid.damages.truncate(0);
-                    continue;
-                }
-                let SDLGrid {
-                    canvas,
-                    atlas,
-                    atlas_index,
-                    atlas_next_slot,
-                    big_texture,
-                    big_texture_copy,
-                    texture_creator,
-                    width,
-                    height,
-                    grid_x_offset,
-                    grid_y_offset,
-                    font_width,
-                    font_height,
-                    ..
-                } = if let Some(g) = sdl_grids.get_mut(key) {
-                    g
-                } else {
-                    sdl_grids.insert(
-                        *key,
-                        SDLGrid::new(&video_subsystem, *key, font_width, font_height),
-                    );
-                    sdl_grids.get_mut(key).unwrap()
-                };
-                // Perform any resize
-                {
-                    let size = canvas.window().size();
-                    if size.0 != *width || size.1 != *height {
-                        let col_count = size.0 / *font_width;
-                        let row_count = size.1 / *font_height;
-                        let pixel_grid_width = col_count * *font_width;
-                        let pixel_grid_height = row_count * *font_height;
-                        let new_x_offset = (size.0 - pixel_grid_width) / 2;
-                        let new_y_offset = (size.1 - pixel_grid_height) / 2;
-                        if (col_count as usize) != grid.get_width()
-                            || (row_count as usize) != grid.get_height()
-                        {
-                            // Let neovim know size changed
-                            if let Err(e) = nvim.ui_try_resize_grid(
-                                i64::try_from(*key).unwrap(),
-                                col_count.into(),
-                                row_count.into(),
-                            ) {
-                                eprintln!("{}", e);
+            if let Some(headless) = headless.as_mut() {
+                let skip = if has_ext_windows { vec![1] } else { vec![] };
+                headless.render(&state, &skip, &fonts)?;
+            } else {
+                for (key, grid) in state.grids.iter() {
+                    if has_ext_windows && *key == 1 {
+                        continue;
+                    }
+                    let sdl_grid = if let Some(g) = sdl_grids.get_mut(key) {
+                        g
+                    } else if state.is_pending_split_child(grid.window_id) {
+                        // Will be created by restore_next_split()
+                        continue;
+                    } else {
+                        sdl_grids.insert(
+                            *key,
+                            SDLGrid::new(
+                                &video_subsystem,
+                                &mut render_driver,
+                                *key,
+                                (!tiled).then(|| (grid.get_width(), grid.get_height())),
+                                status_row,
+                                font_width,
+                                font_height,
+                            ),
+                        );
+                        sdl_grids.get_mut(key).unwrap()
+                    };
+                    // Windows keep their initial title until they're marked, the window manager
+                    // needs it to find them.
+                    if wm.is_none() || marked_grids.contains(key) {
+                        let title = if has_ext_windows {
+                            title::expand(&title_template, grid, &state.title)
+                        } else if state.title.is_empty() {
+                            "nwin".to_string()
+                        } else {
+                            state.title.clone()
+                        };
+                        if sdl_grid.canvas.window().title() != title {
+                            if let Err(e) = sdl_grid.canvas.window_mut().set_title(&title) {
+                                eprintln!("Failed to set window title: {}", e);
                             }
                         }
-                        // Resize sdl grid
-                        let min_width = std::cmp::min(size.0, *width);
-                        let min_height = std::cmp::min(size.1, *height);
-                        // back up big_texture to big_texture_copy
-                        let backup_rectangle = Rect::new(0, 0, min_width, min_height);
-                        canvas
-                            .with_texture_canvas(big_texture_copy, |canvas| {
-                                let from = Rect::new(
-                                    *grid_x_offset as i32,
-                                    *grid_y_offset as i32,
-                                    min_width,
-                                    min_height,
-                                );
-                                canvas.copy(big_texture, from, backup_rectangle).unwrap();
-                            })
-                            .unwrap();
-                        // deallocate big_texture
-                        // drop(big_texture);
-                        // allocate new big_texture
-                        *big_texture = texture_creator
-                            .create_texture_target(None, size.0, size.1)
-                            .unwrap();
-                        // restore backup
-                        canvas
-                            .with_texture_canvas(big_texture, |canvas| {
-                                canvas.set_draw_color(default_bg.unwrap());
-                                canvas.clear();
-                                let to = Rect::new(
-                                    new_x_offset as i32,
-                                    new_y_offset as i32,
-                                    min_width,
-                                    min_height,
-                                );
-                                canvas.copy(big_texture_copy, backup_rectangle, to).unwrap();
-                            })
-                            .unwrap();
-                        // destroy backup buffer
-                        // drop(big_texture_copy);
-                        // allocate new backup buffer
-                        *big_texture_copy = texture_creator
-                            .create_texture_target(None, size.0, size.1)
-                            .unwrap();
-                        *width = size.0;
-                        *height = size.1;
-                        *grid_x_offset = new_x_offset;
-                        *grid_y_offset = new_y_offset;
                     }
-                }
-
-                if grid.get_width() > 0 && grid.get_height() > 0 {
-                    for d in &grid.damages {
-                        if let Damage::Cell {
-                            row,
-                            column,
-                            width,
-                            height,
-                        } = d
+                    // Perform any resize
+                    let size = sdl_grid.canvas.window().size();
+                    if size.0 != sdl_grid.width || size.1 != sdl_grid.height {
+                        let (col_count, row_count) =
+                            sdl_grid.resize(size.0, size.1, default_bg.unwrap(), status_row);
+                        if (col_count as usize) != grid.get_width()
+                            || (row_count as usize) != grid.get_height()
                         {
-                            let damage_top = *row;
-                            let mut damage_bottom = row + height;
-                            if damage_bottom > grid.get_height() {
-                                damage_bottom = grid.get_height();
-                            }
-                            for current_row in damage_top..damage_bottom {
-                                let damage_left = *column;
-                                let mut damage_right = column + width;
-                                if damage_right > grid.get_width() {
-                                    damage_right = grid.get_width();
-                                }
-                                for current_column in damage_left..damage_right {
-                                    let atlas_key = grid.chars[current_row][current_column]
-                                        .or_else(|| Some(0 as char))
-                                        .unwrap();
-                                    let attr_id = grid.colors[current_row][current_column];
-                                    if let None = atlas_index.get(&atlas_key) {
-                                        canvas
-                                            .with_texture_canvas(atlas, |canvas| {
-                                                if let Some(char) =
-                                                    grid.chars[current_row][current_column]
-                                                {
-                                                    let surface = font
-                                                        .render(&char.to_string())
-                                                        .blended(WHITE)
-                                                        .map_err(|e| e.to_string())
-                                                        .unwrap();
-                                                    let cell_rect = Rect::new(
-                                                        *atlas_next_slot,
-                                                        0,
-                                                        surface.width(),
-                                                        surface.height(),
-                                                    );
-                                                    let glyph = surface
-                                                        .into_canvas()
-                                                        .unwrap();
-                                                    let mut texture = texture_creator
-                                                        .create_texture_target(PixelFormatEnum::ARGB8888, cell_rect.width(), cell_rect.height())
-                                                        .unwrap();
-                                                    texture.update(None, &glyph.read_pixels(None, PixelFormatEnum::ARGB8888).unwrap(), 4 * cell_rect.width() as usize).unwrap();
-                                                    canvas.set_draw_color(TRANSPARENT);
-                                                    canvas.fill_rect(cell_rect).unwrap();
-                                                    canvas.copy(&texture, None, cell_rect).unwrap();
-                                                    atlas_index.insert(
-                                                        atlas_key,
-                                                        (*atlas_next_slot, cell_rect.width()),
-                                                    );
-                                                    *atlas_next_slot += cell_rect.width() as i32;
-                                                    if *atlas_next_slot > (MAX_TEXTURE_SIZE as i32) {
-                                                        eprintln!("Texture atlas is full!");
-                                                    }
-                                                } else {
-                                                    let cell_rect = Rect::new(
-                                                        *atlas_next_slot,
-                                                        0,
-                                                        *font_width,
-                                                        *font_height,
-                                                    );
-                                                    canvas.set_draw_color(TRANSPARENT);
-                                                    canvas.fill_rect(cell_rect).unwrap();
-
-                                                    atlas_index.insert(
-                                                        atlas_key,
-                                                        (*atlas_next_slot, *font_width),
-                                                    );
-                                                    *atlas_next_slot += *font_width as i32;
-                                                    if *atlas_next_slot > (MAX_TEXTURE_SIZE as i32) {
-                                                        eprintln!("Texture atlas is full!");
-                                                    }
-                                                }
-                                            })
-                                            .unwrap();
-                                    }
-                                    let hl_attr = state.hl_attrs.get(&attr_id).unwrap();
-                                    let (pos, width) = atlas_index.get(&atlas_key).unwrap();
-                                    canvas
-                                        .with_texture_canvas(big_texture, |canvas| {
-                                            let mut bg = hl_attr
-                                                .background
-                                                .or_else(|| default_bg)
-                                                .unwrap();
-                                            let mut fg = hl_attr
-                                                .foreground
-                                                .or_else(|| default_fg)
-                                                .unwrap();
-                                            if hl_attr.reverse {
-                                                let tmp = bg;
-                                                bg = fg;
-                                                fg = tmp;
-                                            }
-
-                                            let from = Rect::new(*pos, 0, *width, *font_height);
-                                            let to = Rect::new(
-                                                (*grid_x_offset as i32)
-                                                    + (current_column as i32)
-                                                        * (*font_width as i32),
-                                                (*grid_y_offset as i32)
-                                                    + (current_row as i32) * (*font_height as i32),
-                                                *width,
-                                                *font_height,
-                                            );
-                                            canvas.set_draw_color(bg);
-                                            canvas.fill_rect(to).unwrap();
-                                            atlas.set_color_mod(fg.r, fg.g, fg.b);
-                                            canvas.copy(&atlas, from, to).unwrap();
-                                        })
-                                        .unwrap();
+                            // Let neovim know size changed
+                            if let Some(nvim) = nvim.as_mut() {
+                                if let Err(e) = nvim.ui_try_resize_grid(
+                                    i64::try_from(*key).unwrap(),
+                                    col_count.into(),
+                                    row_count.into(),
+                                ) {
+                                    eprintln!("{}", e);
                                 }
                             }
-                        } else if let Damage::VerticalScroll { from_row, to_row, height, from_col, width } = d {
-                            canvas
-                                .with_texture_canvas(big_texture_copy, |canvas| {
-                                    canvas.copy(&big_texture, None, None).unwrap();
-                                })
-                                .unwrap();
-                            canvas
-                                .with_texture_canvas(big_texture, |canvas| {
-                                    let f = Rect::new(
-                                        (*from_col as i32) * (*font_width as i32),
-                                        (*grid_y_offset as i32)
-                                            + (*from_row as i32) * (*font_height as i32),
-                                        (*width as u32) * (*font_width as u32),
-                                        (*height as u32) * (*font_height as u32),
-                                    );
-                                    let t = Rect::new(
-                                        (*from_col as i32) * (*font_width as i32),
-                                        (*grid_y_offset as i32)
-                                            + (*to_row as i32) * (*font_height as i32),
-                                        (*width as u32) * (*font_width as u32),
-                                        (*height as u32) * (*font_height as u32),
-                                    );
-                                    canvas.copy(&big_texture_copy, f, t).unwrap();
-                                })
-                                .unwrap();
-                        } else if let Damage::Destroy {} = d {
-                            grids_to_destroy.push(*key);
                         }
                     }
-                    let r = Rect::new(0, 0, *width, *height);
-                    canvas.copy(&big_texture, r, r).unwrap();
-
-                    if *key == state.cursor_grid {
-                        if state.cmdline_shown {
-                            canvas.set_draw_color(default_bg.unwrap());
-                            let cmdline_rect = Rect::new(0, 0, *width, *font_height);
-                            canvas.fill_rect(cmdline_rect).unwrap();
-                            let s = state.cmdline_firstc.to_string() + &state.cmdline_content;
-                            let msg = font
-                                .render(&s)
-                                .blended(default_fg.unwrap())
-                                .map_err(|e| e.to_string())
-                                .unwrap();
-                            let texture = texture_creator
-                                .create_texture_from_surface(&msg)
-                                .map_err(|e| e.to_string())
-                                .unwrap();
-                            let q = texture.query();
-                            canvas
-                                .copy(&texture, None, Rect::new(0, 0, q.width, q.height))
-                                .unwrap();
-                        } else if state.cursor_on {
-                            let (row, column) = grid.get_cursor_pos();
-                            let attr_id = grid.colors[row as usize][column as usize];
-                            if let Some(hl_attr) = state.hl_attrs.get(&attr_id) {
-                                canvas.set_draw_color(
-                                    hl_attr.foreground.or_else(|| default_fg).unwrap(),
-                                );
-                                cursor_rect.set_x(
-                                    (*grid_x_offset as i32)
-                                        + (column as i32) * (*font_width as i32),
-                                );
-                                cursor_rect.set_y(
-                                    (*grid_y_offset as i32) + (row as i32) * (*font_height as i32),
-                                );
-                                cursor_rect.set_width(*font_width);
-                                cursor_rect.set_height(*font_height);
-                                canvas.fill_rect(cursor_rect).unwrap();
-                                if let Some(c) = grid.chars[row as usize][column as usize] {
-                                    let (pos, width) = atlas_index.get(&c).unwrap();
-                                    let color = hl_attr.background.or_else(|| default_bg).unwrap();
-                                    atlas.set_color_mod(color.r, color.g, color.b);
-                                    let from = Rect::new(*pos, 0, *width, *font_height);
-                                    canvas.copy(&atlas, from, cursor_rect).unwrap();
-                                }
-                            }
-                        }
-                        for i in 0..state.message_contents.len() {
-                            if let Some(attr) = state.hl_attrs.get(&state.message_attrs[i]) {
-                                let s = &state.message_contents[i];
-                                let msg = font
-                                    .render(&s)
-                                    .shaded(
-                                        attr.foreground.or_else(|| default_fg).unwrap(),
-                                        attr.background.or_else(|| default_bg).unwrap(),
-                                    )
-                                    .map_err(|e| e.to_string())
-                                    .unwrap();
-                                let texture = texture_creator
-                                    .create_texture_from_surface(&msg)
-                                    .map_err(|e| e.to_string())
-                                    .unwrap();
-                                let q = texture.query();
-                                canvas
-                                    .copy(
-                                        &texture,
-                                        None,
-                                        Rect::new(
-                                            0,
-                                            (i as i32) * (q.height as i32),
-                                            q.width,
-                                            q.height,
-                                        ),
-                                    )
-                                    .unwrap();
-                            }
+                    sdl_grid.draw(
+                        grid,
+                        *key == state.cursor_grid,
+                        &state,
+                        &fonts,
+                        smooth_scroll,
+                    );
+                    sdl_grid.canvas.present();
+                    if print_fps {
+                        frame_count += 1;
+                        if last_second.elapsed().as_secs() > 0 {
+                            println!("{} fps", frame_count);
+                            frame_count = 0;
+                            last_second = Instant::now();
                         }
                     }
                 }
-                canvas.present();
-                if print_fps {
-                    frame_count += 1;
-                    if last_second.elapsed().as_secs() > 0 {
-                        println!("{} fps", frame_count);
-                        frame_count = 0;
-                        last_second = Instant::now();
-                    }
+                if let Some(window) = messages_window.as_mut() {
+                    window.draw(&state, &fonts);
+                }
+            }
+            for (key, grid) in state.grids.iter_mut() {
+                if grid.damages.iter().any(|d| matches!(d, Damage::Destroy {})) {
+                    grids_to_destroy.push(*key);
                 }
                 grid.damages.truncate(0);
+                grid.viewport_scrolled = false;
             }
-            let time_since_last_message = (Instant::now() - state.message_time).as_millis();
-            if state.has_moved_since_last_message && time_since_last_message > 3000 {
+            if state.messages.expired() {
                 state.msg_clear();
             }
             for key in &grids_to_destroy {
                 sdl_grids.remove(&key);
+                marked_grids.remove(&key);
+                if let Some(headless) = headless.as_mut() {
+                    headless.remove(*key);
+                }
                 state.grids.remove(&key);
             }
         }
 
+        // There is no UI to read events from in headless mode, just wait for the next frame.
+        if headless.is_some() {
+            let frame_time = Duration::from_millis((1000 / max_fps) as u64);
+            if let Some(time_left) = frame_time.checked_sub(now.elapsed()) {
+                std::thread::sleep(time_left);
+            }
+            continue;
+        }
+
         // Use the time we have left before having to display the next frame to read events from
         // ui and forward them to neovim if necessary.
         let mut time_left = (1000 / max_fps) - i64::try_from(now.elapsed().as_millis()).map_or(0, |v| v);
@@ -1419,11 +1757,25 @@ pub fn main() -> Result<(), String> {
             if let Some(event) = event_pump.wait_event_timeout(time_left as u32) {
                 match event {
                     Event::Quit { .. } => {
-                        nvim.quit_no_save().unwrap();
+                        if let Some(nvim) = nvim.as_mut() {
+                            // Servers we attached to outlive nwin, only nvim --embed instances
+                            // are ours to quit.
+                            if server_address.is_some() {
+                                detach(nvim, chan_id);
+                            } else {
+                                nvim.quit_no_save().unwrap();
+                            }
+                        }
                         break 'running;
                     }
                     Event::KeyDown { timestamp, .. } => {
                         if let Some(str) = keys::nvim_event_representation(event) {
+                            if server_address.is_some() && str == detach_key {
+                                if let Some(nvim) = nvim.as_mut() {
+                                    detach(nvim, chan_id);
+                                }
+                                break 'running;
+                            }
                             input_string.push_str(&str);
                             last_keydown_timestamp = timestamp;
                         }
@@ -1445,6 +1797,80 @@ pub fn main() -> Result<(), String> {
                             }
                         }
                     }
+                    Event::MouseButtonDown {
+                        window_id,
+                        mouse_btn,
+                        x,
+                        y,
+                        ..
+                    }
+                    | Event::MouseButtonUp {
+                        window_id,
+                        mouse_btn,
+                        x,
+                        y,
+                        ..
+                    } if state.mouse_enabled => {
+                        let is_press = matches!(event, Event::MouseButtonDown { .. });
+                        if let (Some(nvim), Some(button), Some(cell)) = (
+                            nvim.as_mut(),
+                            keys::nvim_mouse_button(mouse_btn),
+                            grid_cell_at(&sdl_grids, window_id, x, y),
+                        ) {
+                            let modifier =
+                                keys::nvim_mouse_modifiers(sdl_context.keyboard().mod_state());
+                            let action = if is_press { "press" } else { "release" };
+                            input_mouse(nvim, button, action, &modifier, cell);
+                            pressed_button = if is_press { Some(button) } else { None };
+                            pressed_cell = cell;
+                        }
+                    }
+                    Event::MouseMotion {
+                        window_id, x, y, ..
+                    } if state.mouse_enabled => {
+                        if let (Some(nvim), Some(button), Some(cell)) = (
+                            nvim.as_mut(),
+                            pressed_button,
+                            grid_cell_at(&sdl_grids, window_id, x, y),
+                        ) {
+                            // Neovim only cares about the mouse entering another cell
+                            if cell != pressed_cell {
+                                let modifier =
+                                    keys::nvim_mouse_modifiers(sdl_context.keyboard().mod_state());
+                                input_mouse(nvim, button, "drag", &modifier, cell);
+                                pressed_cell = cell;
+                            }
+                        }
+                    }
+                    Event::MouseWheel {
+                        window_id,
+                        x,
+                        y,
+                        direction,
+                        ..
+                    } if state.mouse_enabled => {
+                        // Wheel events don't say where the pointer is
+                        let mouse = event_pump.mouse_state();
+                        if let (Some(nvim), Some(cell)) = (
+                            nvim.as_mut(),
+                            grid_cell_at(&sdl_grids, window_id, mouse.x(), mouse.y()),
+                        ) {
+                            let modifier =
+                                keys::nvim_mouse_modifiers(sdl_context.keyboard().mod_state());
+                            let sign = if direction == MouseWheelDirection::Flipped {
+                                -1
+                            } else {
+                                1
+                            };
+                            let (x, y) = (x * sign, y * sign);
+                            let scrolls = [("up", y), ("down", -y), ("right", x), ("left", -x)];
+                            for (action, amount) in scrolls.iter() {
+                                for _ in 0..*amount {
+                                    input_mouse(nvim, "wheel", action, &modifier, cell);
+                                }
+                            }
+                        }
+                    }
                     Event::Window {
                         window_id,
                         win_event,
@@ -1453,10 +1879,22 @@ pub fn main() -> Result<(), String> {
                         // When a window closes down, Hidden and FocusLost are sent, but we've
                         // already gotten rid of the grid, so we won't be able to find it in sdl_grids.
                         // That's why we let Some(...) = instead of .unwrap()'ing.
-                        if let Some((key, _)) = sdl_grids
-                            .iter_mut()
-                            .find(|(_, v)| v.canvas.window().id() == window_id)
-                        {
+                        // There's also no neovim to notify when replaying a recording.
+                        let is_messages_window = messages_window
+                            .as_ref()
+                            .is_some_and(|w| w.canvas.window().id() == window_id);
+                        if is_messages_window {
+                            // Closing the messages window brings messages back over the grids
+                            if let WindowEvent::Close = win_event {
+                                messages_window = None;
+                                state.messages.windowed = false;
+                            }
+                        } else if let (Some((key, _)), Some(nvim)) = (
+                            sdl_grids
+                                .iter_mut()
+                                .find(|(_, v)| v.canvas.window().id() == window_id),
+                            nvim.as_mut(),
+                        ) {
                             match win_event {
                                 WindowEvent::Close => {
                                     if let Some(grid) = state.grids.get(key) {
@@ -1497,13 +1935,168 @@ pub fn main() -> Result<(), String> {
                 }
             }
             if input_string != "" {
-                if let Err(_) = nvim.input(&input_string) {
-                    eprintln!("nvim_input('{}') failed", &input_string);
+                if let Some(nvim) = nvim.as_mut() {
+                    if let Err(_) = nvim.input(&input_string) {
+                        eprintln!("nvim_input('{}') failed", &input_string);
+                    }
                 }
             }
             time_left = (1000 / max_fps) - i64::try_from(now.elapsed().as_millis()).map_or(0, |v| v);
         }
     }
 
+    if let (Some(headless), Some(dir)) = (&headless, &headless_dir) {
+        headless.save_all(dir)?;
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRID: NvimGridId = 1;
+
+    /// A 4x4 grid whose cells hold their coordinates, e.g. 12 for row 1, column 2, both as text
+    /// and as color.
+    fn numbered_state() -> NvimState {
+        let mut state = NvimState::new();
+        let mut grid = NvimGrid::new(4, 4);
+        for row in 0..4 {
+            for column in 0..4 {
+                grid.cells[row][column] = (10 * row + column) as CellText;
+                grid.colors[row][column] = (10 * row + column) as u64;
+            }
+        }
+        state.grids.insert(GRID, grid);
+        state
+    }
+
+    /// Checks that both the texts and the colors of the grid are `expected`.
+    fn assert_grid(state: &NvimState, expected: [[u32; 4]; 4]) {
+        let grid = &state.grids[&GRID];
+        for (row, expected) in expected.iter().enumerate() {
+            assert_eq!(grid.cells[row], *expected, "texts of row {}", row);
+            let colors: Vec<u64> = expected.iter().map(|c| *c as u64).collect();
+            assert_eq!(grid.colors[row], colors, "colors of row {}", row);
+        }
+    }
+
+    #[test]
+    fn scroll_up() {
+        let mut state = numbered_state();
+        state.grid_scroll(GRID, 0, 4, 0, 4, 1, 0);
+        assert_grid(
+            &state,
+            [
+                [10, 11, 12, 13],
+                [20, 21, 22, 23],
+                [30, 31, 32, 33],
+                [30, 31, 32, 33],
+            ],
+        );
+    }
+
+    #[test]
+    fn scroll_down() {
+        let mut state = numbered_state();
+        state.grid_scroll(GRID, 0, 4, 0, 4, -2, 0);
+        assert_grid(
+            &state,
+            [
+                [0, 1, 2, 3],
+                [10, 11, 12, 13],
+                [0, 1, 2, 3],
+                [10, 11, 12, 13],
+            ],
+        );
+    }
+
+    #[test]
+    fn scroll_left() {
+        let mut state = numbered_state();
+        state.grid_scroll(GRID, 0, 4, 0, 4, 0, 1);
+        assert_grid(
+            &state,
+            [
+                [1, 2, 3, 3],
+                [11, 12, 13, 13],
+                [21, 22, 23, 23],
+                [31, 32, 33, 33],
+            ],
+        );
+    }
+
+    #[test]
+    fn scroll_right() {
+        let mut state = numbered_state();
+        state.grid_scroll(GRID, 0, 4, 0, 4, 0, -1);
+        assert_grid(
+            &state,
+            [
+                [0, 0, 1, 2],
+                [10, 10, 11, 12],
+                [20, 20, 21, 22],
+                [30, 30, 31, 32],
+            ],
+        );
+    }
+
+    #[test]
+    fn scroll_diagonally() {
+        let mut state = numbered_state();
+        state.grid_scroll(GRID, 0, 4, 0, 4, 1, -1);
+        assert_grid(
+            &state,
+            [
+                [0, 10, 11, 12],
+                [10, 20, 21, 22],
+                [20, 30, 31, 32],
+                [30, 31, 32, 33],
+            ],
+        );
+    }
+
+    #[test]
+    fn scroll_region() {
+        let mut state = numbered_state();
+        state.grid_scroll(GRID, 1, 3, 1, 3, -1, 0);
+        assert_grid(
+            &state,
+            [
+                [0, 1, 2, 3],
+                [10, 11, 12, 13],
+                [20, 11, 12, 23],
+                [30, 31, 32, 33],
+            ],
+        );
+        match state.grids[&GRID].damages.last() {
+            Some(Damage::Scroll {
+                from_row: 1,
+                from_col: 1,
+                to_row: 2,
+                to_col: 1,
+                width: 2,
+                height: 1,
+            }) => {}
+            d => panic!("unexpected damage {:?}", d),
+        }
+    }
+
+    #[test]
+    fn scroll_past_region() {
+        let mut state = numbered_state();
+        state.grid_scroll(GRID, 1, 3, 0, 4, 2, 0);
+        assert_grid(
+            &state,
+            [
+                [0, 1, 2, 3],
+                [10, 11, 12, 13],
+                [20, 21, 22, 23],
+                [30, 31, 32, 33],
+            ],
+        );
+        assert!(state.grids[&GRID].damages.is_empty());
+    }
+}
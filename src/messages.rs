@@ -0,0 +1,211 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use crate::interner::{Cells, Interner, EMPTY};
+use crate::redraw::Chunk;
+
+/// Most messages kept in the history, older ones are dropped.
+pub const HISTORY_SIZE: usize = 200;
+
+/// How long messages stay over the grid once the cursor has moved, in milliseconds.
+const TIMEOUT: u128 = 3000;
+
+#[derive(Clone)]
+pub struct Message {
+    /// See :help ui-messages, e.g. "emsg" for errors or "return_prompt" for "Press ENTER"
+    pub kind: String,
+    /// The lines of the message, before wrapping
+    lines: Vec<Cells>,
+}
+
+impl Message {
+    pub fn new(kind: String, content: &[Chunk], interner: &mut Interner) -> Message {
+        let mut lines = vec![vec![]];
+        for chunk in content {
+            for (i, text) in chunk.text.split('\n').enumerate() {
+                if i > 0 {
+                    lines.push(vec![]);
+                }
+                let cells = interner.layout(text);
+                let last = lines.len() - 1;
+                lines[last].extend(cells.into_iter().map(|(_, text)| (text, chunk.attr_id)));
+            }
+        }
+        Message { kind, lines }
+    }
+
+    /// The highlight group of the parts of the message neovim didn't highlight, depending on its
+    /// kind.
+    pub fn hl_group(&self) -> Option<&'static str> {
+        match self.kind.as_str() {
+            "emsg" | "echoerr" | "lua_error" | "rpc_error" => Some("ErrorMsg"),
+            "wmsg" => Some("WarningMsg"),
+            "confirm" | "confirm_sub" => Some("Question"),
+            "return_prompt" => Some("MoreMsg"),
+            _ => None,
+        }
+    }
+
+    /// Whether neovim is waiting for an answer to the message. Prompts stay until neovim clears
+    /// them and aren't part of the history.
+    pub fn is_prompt(&self) -> bool {
+        matches!(
+            self.kind.as_str(),
+            "confirm" | "confirm_sub" | "return_prompt"
+        )
+    }
+
+    /// The lines of the message, wrapped after `cols` cells. Double-width characters that don't
+    /// fit at the end of a line go to the next one. Unhighlighted parts get `default_attr`.
+    pub fn cells(&self, default_attr: u64, cols: usize) -> Vec<Cells> {
+        let mut lines = vec![];
+        for line in &self.lines {
+            let mut wrapped = vec![];
+            for (i, (text, attr_id)) in line.iter().enumerate() {
+                let attr_id = if *attr_id == 0 {
+                    default_attr
+                } else {
+                    *attr_id
+                };
+                if *text != EMPTY {
+                    let width = if line.get(i + 1).map(|c| c.0) == Some(EMPTY) {
+                        2
+                    } else {
+                        1
+                    };
+                    if cols > 0 && !wrapped.is_empty() && wrapped.len() + width > cols {
+                        lines.push(wrapped);
+                        wrapped = vec![];
+                    }
+                }
+                wrapped.push((*text, attr_id));
+            }
+            lines.push(wrapped);
+        }
+        lines
+    }
+}
+
+pub struct Messages {
+    /// Messages shown since neovim last cleared them
+    pub shown: Vec<Message>,
+    /// The output of :messages, shown until neovim clears messages
+    pub history_shown: Vec<Message>,
+    /// Every message shown so far, most recent last
+    pub history: VecDeque<Message>,
+    /// Whether messages are drawn in their own OS window instead of over the cursor grid
+    pub windowed: bool,
+    shown_at: Instant,
+    cursor_moved: bool,
+    /// Whether the last message shown is at the end of the history, so that it can be replaced
+    last_in_history: bool,
+}
+
+impl Messages {
+    pub fn new() -> Messages {
+        Messages {
+            shown: vec![],
+            history_shown: vec![],
+            history: VecDeque::new(),
+            windowed: false,
+            shown_at: Instant::now(),
+            cursor_moved: false,
+            last_in_history: false,
+        }
+    }
+
+    pub fn show(&mut self, message: Message, replace_last: bool) {
+        if replace_last {
+            self.shown.pop();
+            if self.last_in_history {
+                self.history.pop_back();
+            }
+        }
+        self.last_in_history = !message.is_prompt() && message.kind != "search_count";
+        if self.last_in_history {
+            self.history.push_back(message.clone());
+            if self.history.len() > HISTORY_SIZE {
+                self.history.pop_front();
+            }
+        }
+        self.shown.push(message);
+        self.shown_at = Instant::now();
+        self.cursor_moved = false;
+    }
+
+    pub fn clear(&mut self) {
+        self.shown.clear();
+        self.history_shown.clear();
+        self.last_in_history = false;
+    }
+
+    pub fn history_show(&mut self, entries: Vec<Message>) {
+        self.history_shown = entries;
+        self.shown_at = Instant::now();
+        self.cursor_moved = false;
+    }
+
+    /// Forgets every message shown so far, as `:messages clear` does.
+    pub fn history_clear(&mut self) {
+        self.history.clear();
+        self.history_shown.clear();
+        self.last_in_history = false;
+    }
+
+    pub fn cursor_moved(&mut self) {
+        self.cursor_moved = true;
+    }
+
+    /// Whether the messages shown have been read: the cursor moved a while after they were
+    /// shown and neovim isn't waiting for an answer.
+    pub fn expired(&self) -> bool {
+        self.cursor_moved
+            && self.shown_at.elapsed().as_millis() > TIMEOUT
+            && !self.shown.iter().any(|m| m.is_prompt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(lines: &[Cells], interner: &Interner) -> Vec<Vec<String>> {
+        lines
+            .iter()
+            .map(|line| line.iter().map(|c| interner.get(c.0).to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn lines_are_split_on_newlines_and_wrapped() {
+        let mut interner = Interner::new();
+        let content = [
+            Chunk {
+                attr_id: 0,
+                text: "abc\nd".to_string(),
+            },
+            Chunk {
+                attr_id: 2,
+                text: "e".to_string(),
+            },
+        ];
+        let lines = Message::new("echo".to_string(), &content, &mut interner).cells(7, 2);
+        assert_eq!(
+            texts(&lines, &interner),
+            [vec!["a", "b"], vec!["c"], vec!["d", "e"]]
+        );
+        let attrs: Vec<u64> = lines[2].iter().map(|c| c.1).collect();
+        assert_eq!(attrs, [7, 2]);
+    }
+
+    #[test]
+    fn double_width_characters_are_not_cut() {
+        let mut interner = Interner::new();
+        let content = [Chunk {
+            attr_id: 0,
+            text: "ab漢".to_string(),
+        }];
+        let lines = Message::new("echo".to_string(), &content, &mut interner).cells(0, 3);
+        assert_eq!(texts(&lines, &interner), [vec!["a", "b"], vec!["漢", ""]]);
+    }
+}
@@ -0,0 +1,90 @@
+use std::ops::Range;
+
+use crate::interner::{Cells, Interner};
+use crate::redraw::{Chunk, PopupmenuItem};
+use crate::{NvimColumn, NvimRow};
+
+/// Most items shown at once, longer menus scroll.
+pub const MAX_HEIGHT: usize = 15;
+
+/// An entry of the menu laid out in cells: its word, kind and menu, and the lines of its info.
+pub struct Item {
+    pub fields: [Cells; 3],
+    pub info: Vec<Cells>,
+}
+
+impl Item {
+    fn new(item: &PopupmenuItem, interner: &mut Interner) -> Item {
+        let mut cells = |text: &str| {
+            interner.cells(&[Chunk {
+                attr_id: 0,
+                text: text.to_string(),
+            }])
+        };
+        Item {
+            fields: [cells(&item.word), cells(&item.kind), cells(&item.menu)],
+            info: item.info.lines().take(MAX_HEIGHT).map(cells).collect(),
+        }
+    }
+}
+
+/// The completion menu, drawn by nwin since neovim can't draw it in the grid it belongs to when
+/// windows are external.
+pub struct Popupmenu {
+    pub items: Vec<Item>,
+    pub selected: Option<usize>,
+    /// Where the completed word starts, the menu is drawn below or above it
+    pub row: NvimRow,
+    pub col: NvimColumn,
+    /// The grid `row` and `col` are relative to, -1 for the cmdline
+    pub grid: i64,
+    /// First item shown
+    first: usize,
+}
+
+impl Popupmenu {
+    pub fn new(
+        items: Vec<PopupmenuItem>,
+        selected: i64,
+        row: NvimRow,
+        col: NvimColumn,
+        grid: i64,
+        interner: &mut Interner,
+    ) -> Popupmenu {
+        let mut menu = Popupmenu {
+            items: items.iter().map(|item| Item::new(item, interner)).collect(),
+            selected: None,
+            row,
+            col,
+            grid,
+            first: 0,
+        };
+        menu.select(selected);
+        menu
+    }
+
+    /// Selects the `selected`th item, -1 meaning none, and scrolls to it.
+    pub fn select(&mut self, selected: i64) {
+        self.selected = if selected >= 0 && (selected as usize) < self.items.len() {
+            Some(selected as usize)
+        } else {
+            None
+        };
+        self.first = self.visible(MAX_HEIGHT).start;
+    }
+
+    /// The items to show when there's room for `height` of them: as close to the ones shown so
+    /// far as possible, as long as the selected item is part of them.
+    pub fn visible(&self, height: usize) -> Range<usize> {
+        let height = std::cmp::min(height, self.items.len());
+        let mut first = std::cmp::min(self.first, self.items.len() - height);
+        if let Some(selected) = self.selected {
+            if selected < first {
+                first = selected;
+            } else if selected >= first + height {
+                first = selected + 1 - height;
+            }
+        }
+        first..first + height
+    }
+}
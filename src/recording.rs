@@ -0,0 +1,111 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use neovim_lib::Value;
+
+/// Recordings are a stream of msgpack values. The first one is a header map, every following one
+/// is a `[microseconds_since_start, method, args]` array describing a notification received from
+/// neovim.
+const RECORDING_VERSION: u64 = 1;
+
+/// Sent on the replay channel once the whole recording has been replayed.
+pub const REPLAY_DONE: &str = "nwin_replay_done";
+
+pub struct RecordingHeader {
+    pub has_ext_windows: bool,
+}
+
+pub struct Recorder {
+    writer: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn new(path: &Path, header: &RecordingHeader) -> Result<Recorder, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut recorder = Recorder {
+            writer: BufWriter::new(file),
+            start: Instant::now(),
+        };
+        recorder.write(&Value::Map(vec![
+            ("version".into(), RECORDING_VERSION.into()),
+            ("ext_windows".into(), header.has_ext_windows.into()),
+        ]));
+        Ok(recorder)
+    }
+
+    fn write(&mut self, value: &Value) {
+        // Flush after every notification: recordings are most useful when nwin crashes.
+        if let Err(e) = rmpv::encode::write_value(&mut self.writer, value)
+            .map_err(|e| e.to_string())
+            .and_then(|_| self.writer.flush().map_err(|e| e.to_string()))
+        {
+            eprintln!("Failed to write recording: {}", e);
+        }
+    }
+
+    pub fn record(&mut self, method: &str, args: &[Value]) {
+        let timestamp = self.start.elapsed().as_micros() as u64;
+        self.write(&Value::Array(vec![
+            timestamp.into(),
+            method.into(),
+            Value::Array(args.to_vec()),
+        ]));
+    }
+}
+
+/// Reads the recording at `path` and returns a channel on which its notifications will be sent
+/// with the same timing as when they were recorded, followed by a REPLAY_DONE notification.
+pub fn replay(path: &Path) -> Result<(RecordingHeader, Receiver<(String, Vec<Value>)>), String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut reader = BufReader::new(file);
+    let header = rmpv::decode::read_value(&mut reader)
+        .map_err(|e| format!("{}: can't read recording header: {}", path.display(), e))?;
+    let mut version = None;
+    let mut has_ext_windows = false;
+    for (key, value) in header.as_map().map(|m| m.as_slice()).unwrap_or(&[]) {
+        match key.as_str() {
+            Some("version") => version = value.as_u64(),
+            Some("ext_windows") => has_ext_windows = value.as_bool().unwrap_or(false),
+            _ => {}
+        }
+    }
+    if version != Some(RECORDING_VERSION) {
+        return Err(format!(
+            "{}: unsupported recording version {:?}",
+            path.display(),
+            version
+        ));
+    }
+
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        let start = Instant::now();
+        while let Ok(entry) = rmpv::decode::read_value(&mut reader) {
+            let (timestamp, method, args) = match entry.as_array().map(|a| a.as_slice()) {
+                Some([Value::Integer(t), Value::String(m), Value::Array(a)])
+                    if t.as_u64().is_some() && m.as_str().is_some() =>
+                {
+                    (t.as_u64().unwrap(), m.as_str().unwrap(), a)
+                }
+                _ => {
+                    eprintln!("Skipping malformed recording entry: {}", entry);
+                    continue;
+                }
+            };
+            let due = Duration::from_micros(timestamp);
+            if let Some(delay) = due.checked_sub(start.elapsed()) {
+                thread::sleep(delay);
+            }
+            if sender.send((method.to_string(), args.clone())).is_err() {
+                return;
+            }
+        }
+        let _ = sender.send((REPLAY_DONE.to_string(), vec![]));
+    });
+    Ok((RecordingHeader { has_ext_windows }, receiver))
+}
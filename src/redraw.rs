@@ -0,0 +1,1043 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::slice::Iter;
+
+use neovim_lib::Value;
+
+use crate::{NvimColumn, NvimGridId, NvimHeight, NvimRow, NvimWidth, NvimWinId};
+
+#[derive(Debug, Clone, Copy)]
+pub enum SplitDirection {
+    Above = 0,
+    Below = 1,
+    Left = 2,
+    Right = 3,
+    BelowRight = 4,
+    AboveLeft = 5,
+    TopLeft = 6,
+    BottomRight = 7,
+    Previous = 8,
+}
+
+impl TryFrom<u64> for SplitDirection {
+    type Error = ();
+
+    fn try_from(v: u64) -> Result<Self, Self::Error> {
+        match v {
+            x if x == SplitDirection::Above as u64 => Ok(SplitDirection::Above),
+            x if x == SplitDirection::Below as u64 => Ok(SplitDirection::Below),
+            x if x == SplitDirection::Left as u64 => Ok(SplitDirection::Left),
+            x if x == SplitDirection::Right as u64 => Ok(SplitDirection::Right),
+            x if x == SplitDirection::BelowRight as u64 => Ok(SplitDirection::BelowRight),
+            x if x == SplitDirection::AboveLeft as u64 => Ok(SplitDirection::AboveLeft),
+            x if x == SplitDirection::TopLeft as u64 => Ok(SplitDirection::TopLeft),
+            x if x == SplitDirection::BottomRight as u64 => Ok(SplitDirection::BottomRight),
+            x if x == SplitDirection::Previous as u64 => Ok(SplitDirection::Previous),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A chunk of highlighted text, as found in cmdline_show and msg_show.
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub attr_id: u64,
+    pub text: String,
+}
+
+/// An entry of the completion menu, see :help complete-items.
+#[derive(Debug, Clone)]
+pub struct PopupmenuItem {
+    pub word: String,
+    pub kind: String,
+    pub menu: String,
+    pub info: String,
+}
+
+#[derive(Debug)]
+pub struct GridLineCell {
+    pub text: String,
+    pub hl_id: Option<u64>,
+    pub repeat: NvimWidth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CursorShape {
+    Block,
+    Horizontal,
+    Vertical,
+}
+
+/// How the cursor looks in a given mode, see `:help guicursor`. Missing keys get the values
+/// neovim uses when 'guicursor' is empty.
+#[derive(Debug, Clone)]
+pub struct ModeInfo {
+    pub name: String,
+    pub cursor_shape: CursorShape,
+    /// Percentage of the cell the cursor occupies, for the horizontal and vertical shapes.
+    pub cell_percentage: u64,
+    pub blinkwait: u64,
+    pub blinkon: u64,
+    pub blinkoff: u64,
+    /// Highlight of the cursor, 0 meaning that the colors of the cell should be reversed.
+    pub attr_id: u64,
+}
+
+impl ModeInfo {
+    pub fn new() -> ModeInfo {
+        ModeInfo {
+            name: String::new(),
+            cursor_shape: CursorShape::Block,
+            cell_percentage: 100,
+            blinkwait: 0,
+            blinkon: 0,
+            blinkoff: 0,
+            attr_id: 0,
+        }
+    }
+}
+
+/// The options of option_set nwin cares about.
+#[derive(Debug)]
+pub enum UiOption {
+    GuiFont(String),
+    LineSpace(i64),
+    Other(String),
+}
+
+#[derive(Debug)]
+pub enum HlAttr {
+    Foreground(Option<u64>),
+    Background(Option<u64>),
+    Special(Option<u64>),
+    Blend(u8),
+    Reverse(bool),
+    Italic(bool),
+    Bold(bool),
+    Strikethrough(bool),
+    Underline(bool),
+    Undercurl(bool),
+    Unsupported(String),
+}
+
+#[derive(Debug)]
+pub enum RedrawEvent {
+    BusyStart,
+    BusyStop,
+    CmdlineBlockAppend {
+        line: Vec<Chunk>,
+    },
+    CmdlineBlockHide,
+    CmdlineBlockShow {
+        lines: Vec<Vec<Chunk>>,
+    },
+    CmdlineHide,
+    CmdlinePos {
+        pos: u64,
+        level: u64,
+    },
+    CmdlineShow {
+        content: Vec<Chunk>,
+        pos: u64,
+        firstc: String,
+        prompt: String,
+        indent: u64,
+        level: u64,
+    },
+    CmdlineSpecialChar {
+        c: String,
+        shift: bool,
+        level: u64,
+    },
+    DefaultColorsSet {
+        rgb_fg: Option<u64>,
+        rgb_bg: Option<u64>,
+        rgb_sp: Option<u64>,
+    },
+    Flush,
+    GridClear {
+        grid: NvimGridId,
+    },
+    GridCursorGoto {
+        grid: NvimGridId,
+        row: NvimRow,
+        column: NvimColumn,
+    },
+    GridDestroy {
+        grid: NvimGridId,
+    },
+    GridLine {
+        grid: NvimGridId,
+        row: NvimRow,
+        col_start: NvimColumn,
+        cells: Vec<GridLineCell>,
+    },
+    GridResize {
+        grid: NvimGridId,
+        width: NvimWidth,
+        height: NvimHeight,
+    },
+    GridScroll {
+        grid: NvimGridId,
+        top: NvimRow,
+        bot: NvimRow,
+        left: NvimColumn,
+        right: NvimColumn,
+        rows: i64,
+        cols: i64,
+    },
+    HlAttrDefine {
+        id: u64,
+        attrs: Vec<HlAttr>,
+    },
+    HlGroupSet {
+        name: String,
+        hl_id: u64,
+    },
+    ModeChange {
+        mode: String,
+        mode_idx: usize,
+    },
+    ModeInfoSet {
+        cursor_style_enabled: bool,
+        modes: Vec<ModeInfo>,
+    },
+    MouseOff,
+    MouseOn,
+    MsgClear,
+    MsgHistoryClear,
+    MsgHistoryShow {
+        /// Kind and content of each message
+        entries: Vec<(String, Vec<Chunk>)>,
+    },
+    MsgRuler {
+        content: Vec<Chunk>,
+    },
+    MsgShow {
+        kind: String,
+        content: Vec<Chunk>,
+        replace_last: bool,
+    },
+    MsgShowcmd {
+        content: Vec<Chunk>,
+    },
+    MsgShowmode {
+        content: Vec<Chunk>,
+    },
+    OptionSet(UiOption),
+    PopupmenuHide,
+    PopupmenuSelect {
+        selected: i64,
+    },
+    PopupmenuShow {
+        items: Vec<PopupmenuItem>,
+        selected: i64,
+        row: NvimRow,
+        col: NvimColumn,
+        /// -1 when completing in the cmdline
+        grid: i64,
+    },
+    SetTitle {
+        title: String,
+    },
+    WinHide {
+        grid: NvimGridId,
+    },
+    WinPos {
+        grid: NvimGridId,
+        win: NvimWinId,
+        start_row: NvimRow,
+        start_col: NvimColumn,
+        width: NvimWidth,
+        height: NvimHeight,
+    },
+    WinSplit {
+        win1: NvimWinId,
+        grid1: NvimGridId,
+        win2: NvimWinId,
+        grid2: NvimGridId,
+        flags: SplitDirection,
+    },
+    WinViewport {
+        grid: NvimGridId,
+        topline: u64,
+        /// Number of lines the window scrolled by, only sent by neovim 0.10+
+        scroll_delta: Option<i64>,
+    },
+    WildmenuHide,
+    WildmenuSelect {
+        selected: i64,
+    },
+    WildmenuShow {
+        items: Vec<String>,
+    },
+    /// Events we know about but do not act on yet.
+    Ignored,
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    NotAnArray(Value),
+    MissingEventName,
+    NonStringEventName(Value),
+    UnknownEvent(String),
+    MissingArgument {
+        event: String,
+        index: usize,
+    },
+    WrongType {
+        event: String,
+        index: usize,
+        expected: &'static str,
+        found: Value,
+    },
+    InvalidValue {
+        event: String,
+        index: usize,
+        reason: String,
+    },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::NotAnArray(v) => write!(f, "expected an array, got {}", v),
+            DecodeError::MissingEventName => write!(f, "redraw batch has no event name"),
+            DecodeError::NonStringEventName(v) => {
+                write!(f, "redraw batch has a non-string event name: {}", v)
+            }
+            DecodeError::UnknownEvent(name) => write!(f, "unknown redraw event '{}'", name),
+            DecodeError::MissingArgument { event, index } => {
+                write!(f, "{}: missing argument {}", event, index)
+            }
+            DecodeError::WrongType {
+                event,
+                index,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{}: argument {} should be {}, got {}",
+                event, index, expected, found
+            ),
+            DecodeError::InvalidValue {
+                event,
+                index,
+                reason,
+            } => write!(f, "{}: argument {} is invalid: {}", event, index, reason),
+        }
+    }
+}
+
+/// Walks the arguments of a single event, producing errors that name the event and the position
+/// of the offending argument.
+struct Args<'a> {
+    event: &'a str,
+    iter: Iter<'a, Value>,
+    index: usize,
+}
+
+impl<'a> Args<'a> {
+    fn new(event: &'a str, args: &'a [Value]) -> Args<'a> {
+        Args {
+            event,
+            iter: args.iter(),
+            index: 0,
+        }
+    }
+    fn wrong_type(&self, expected: &'static str, found: &Value) -> DecodeError {
+        DecodeError::WrongType {
+            event: self.event.to_string(),
+            index: self.index - 1,
+            expected,
+            found: found.clone(),
+        }
+    }
+    fn invalid(&self, reason: String) -> DecodeError {
+        DecodeError::InvalidValue {
+            event: self.event.to_string(),
+            index: self.index - 1,
+            reason,
+        }
+    }
+    fn next(&mut self) -> Result<&'a Value, DecodeError> {
+        self.index += 1;
+        self.iter
+            .next()
+            .ok_or_else(|| DecodeError::MissingArgument {
+                event: self.event.to_string(),
+                index: self.index - 1,
+            })
+    }
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        let v = self.next()?;
+        v.as_u64()
+            .ok_or_else(|| self.wrong_type("an unsigned integer", v))
+    }
+    fn usize(&mut self) -> Result<usize, DecodeError> {
+        Ok(self.u64()? as usize)
+    }
+    fn i64(&mut self) -> Result<i64, DecodeError> {
+        let v = self.next()?;
+        v.as_i64().ok_or_else(|| self.wrong_type("an integer", v))
+    }
+    fn bool(&mut self) -> Result<bool, DecodeError> {
+        let v = self.next()?;
+        v.as_bool().ok_or_else(|| self.wrong_type("a boolean", v))
+    }
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let v = self.next()?;
+        v.as_str()
+            .map(|s| s.to_string())
+            .ok_or_else(|| self.wrong_type("a string", v))
+    }
+    fn strings(&mut self) -> Result<Vec<String>, DecodeError> {
+        let arr = self.array()?;
+        arr.iter()
+            .map(|v| {
+                v.as_str()
+                    .map(|s| s.to_string())
+                    .ok_or_else(|| self.wrong_type("a string", v))
+            })
+            .collect()
+    }
+    fn array(&mut self) -> Result<&'a Vec<Value>, DecodeError> {
+        let v = self.next()?;
+        v.as_array().ok_or_else(|| self.wrong_type("an array", v))
+    }
+    fn map(&mut self) -> Result<&'a Vec<(Value, Value)>, DecodeError> {
+        let v = self.next()?;
+        v.as_map().ok_or_else(|| self.wrong_type("a map", v))
+    }
+    /// Optional trailing integer, nil meaning "not set".
+    fn optional_u64(&mut self) -> Result<Option<u64>, DecodeError> {
+        match self.iter.clone().next() {
+            None => Ok(None),
+            Some(Value::Nil) => {
+                self.next()?;
+                Ok(None)
+            }
+            Some(_) => self.u64().map(Some),
+        }
+    }
+    /// Optional trailing signed integer, nil meaning "not set".
+    fn optional_i64(&mut self) -> Result<Option<i64>, DecodeError> {
+        match self.iter.clone().next() {
+            None => Ok(None),
+            Some(Value::Nil) => {
+                self.next()?;
+                Ok(None)
+            }
+            Some(_) => self.i64().map(Some),
+        }
+    }
+    /// Windows are sent as msgpack ext types by recent servers, but older servers used plain
+    /// integers.
+    fn window(&mut self) -> Result<NvimWinId, DecodeError> {
+        let v = self.next()?;
+        if let Some(id) = v.as_u64() {
+            return Ok(id);
+        }
+        // neovim-lib doesn't unpack ext types so we end up having to do it ourselves.
+        // https://github.com/msgpack/msgpack/blob/master/spec.md#extension-types
+        // https://github.com/msgpack/msgpack/blob/master/spec.md#int-format-family
+        if let Some((t, values)) = v.as_ext() {
+            // 1 is the id for the window type
+            if t != 1 {
+                return Err(self.invalid(format!("ext type {} is not a window", t)));
+            }
+            return decode_msgpack_uint(values)
+                .ok_or_else(|| self.invalid(format!("can't decode window id {:?}", values)));
+        }
+        Err(self.wrong_type("a window", v))
+    }
+    fn chunks(&mut self) -> Result<Vec<Chunk>, DecodeError> {
+        let arr = self.array()?;
+        self.decode_chunks(arr)
+    }
+    /// An array of lines, each line being an array of chunks.
+    fn lines(&mut self) -> Result<Vec<Vec<Chunk>>, DecodeError> {
+        let arr = self.array()?;
+        let mut lines = Vec::with_capacity(arr.len());
+        for line in arr {
+            let line = line
+                .as_array()
+                .ok_or_else(|| self.wrong_type("an array of lines", line))?;
+            lines.push(self.decode_chunks(line)?);
+        }
+        Ok(lines)
+    }
+    fn decode_chunks(&self, arr: &[Value]) -> Result<Vec<Chunk>, DecodeError> {
+        let mut result = Vec::with_capacity(arr.len());
+        for chunk in arr {
+            let parts = chunk
+                .as_array()
+                .ok_or_else(|| self.wrong_type("an array of [attr_id, text] chunks", chunk))?;
+            match (
+                parts.first().and_then(|v| v.as_u64()),
+                parts.get(1).and_then(|v| v.as_str()),
+            ) {
+                (Some(attr_id), Some(text)) => result.push(Chunk {
+                    attr_id,
+                    text: text.to_string(),
+                }),
+                _ => return Err(self.wrong_type("an [attr_id, text] chunk", chunk)),
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn decode_msgpack_uint(bytes: &[u8]) -> Option<u64> {
+    match bytes.split_first() {
+        // positive fixint
+        Some((&b, [])) if b < 0x80 => Some(b as u64),
+        // uint 8/16/32/64
+        Some((0xCC, rest)) | Some((0xCD, rest)) | Some((0xCE, rest)) | Some((0xCF, rest))
+            if rest.len() == 1 << (bytes[0] - 0xCC) =>
+        {
+            Some(rest.iter().fold(0, |acc, b| acc << 8 | (*b as u64)))
+        }
+        _ => None,
+    }
+}
+
+fn decode_cells(args: &mut Args<'_>) -> Result<Vec<GridLineCell>, DecodeError> {
+    let arr = args.array()?;
+    let mut cells = Vec::with_capacity(arr.len());
+    for cell in arr {
+        let parts = cell
+            .as_array()
+            .ok_or_else(|| args.wrong_type("an array of cells", cell))?;
+        let text = parts
+            .first()
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| args.wrong_type("a cell starting with a string", cell))?;
+        let hl_id = match parts.get(1) {
+            None => None,
+            Some(v) => Some(
+                v.as_u64()
+                    .ok_or_else(|| args.wrong_type("a cell with an integer hl_id", cell))?,
+            ),
+        };
+        let repeat = match parts.get(2) {
+            None => 1,
+            Some(v) => v
+                .as_u64()
+                .ok_or_else(|| args.wrong_type("a cell with an integer repeat", cell))?,
+        } as NvimWidth;
+        cells.push(GridLineCell {
+            text: text.to_string(),
+            hl_id,
+            repeat,
+        });
+    }
+    Ok(cells)
+}
+
+fn decode_hl_attrs(args: &mut Args<'_>) -> Result<Vec<HlAttr>, DecodeError> {
+    let map = args.map()?;
+    let mut attrs = Vec::with_capacity(map.len());
+    for (k, v) in map {
+        let key = k
+            .as_str()
+            .ok_or_else(|| args.wrong_type("a map with string keys", k))?;
+        let color = || v.as_u64();
+        let flag = || {
+            v.as_bool()
+                .ok_or_else(|| args.wrong_type("a boolean attribute", v))
+        };
+        attrs.push(match key {
+            "foreground" => HlAttr::Foreground(color()),
+            "background" => HlAttr::Background(color()),
+            "special" => HlAttr::Special(color()),
+            "blend" => HlAttr::Blend(
+                v.as_u64()
+                    .ok_or_else(|| args.wrong_type("an integer blend", v))? as u8,
+            ),
+            "reverse" => HlAttr::Reverse(flag()?),
+            "italic" => HlAttr::Italic(flag()?),
+            "bold" => HlAttr::Bold(flag()?),
+            "strikethrough" => HlAttr::Strikethrough(flag()?),
+            "underline" => HlAttr::Underline(flag()?),
+            "undercurl" => HlAttr::Undercurl(flag()?),
+            // Only useful for UIs running in terminals
+            "fg_indexed" | "bg_indexed" => continue,
+            _ => HlAttr::Unsupported(key.to_string()),
+        });
+    }
+    Ok(attrs)
+}
+
+fn decode_mode_infos(args: &mut Args<'_>) -> Result<Vec<ModeInfo>, DecodeError> {
+    let arr = args.array()?;
+    let mut modes = Vec::with_capacity(arr.len());
+    for mode in arr {
+        let map = mode
+            .as_map()
+            .ok_or_else(|| args.wrong_type("an array of mode maps", mode))?;
+        let mut info = ModeInfo::new();
+        for (k, v) in map {
+            let number = || {
+                v.as_u64()
+                    .ok_or_else(|| args.wrong_type("an integer mode property", v))
+            };
+            match k.as_str() {
+                Some("name") => info.name = v.as_str().unwrap_or("").to_string(),
+                Some("cursor_shape") => {
+                    info.cursor_shape = match v.as_str() {
+                        Some("block") => CursorShape::Block,
+                        Some("horizontal") => CursorShape::Horizontal,
+                        Some("vertical") => CursorShape::Vertical,
+                        _ => return Err(args.wrong_type("a cursor shape", v)),
+                    }
+                }
+                Some("cell_percentage") => info.cell_percentage = number()?,
+                Some("blinkwait") => info.blinkwait = number()?,
+                Some("blinkon") => info.blinkon = number()?,
+                Some("blinkoff") => info.blinkoff = number()?,
+                Some("attr_id") => info.attr_id = number()?,
+                // short_name, mouse_shape, attr_id_lm...
+                _ => {}
+            }
+        }
+        modes.push(info);
+    }
+    Ok(modes)
+}
+
+/// Entries are [kind, content] arrays, neovim 0.11+ adds an `append` flag we don't need.
+fn decode_history_entries(args: &mut Args<'_>) -> Result<Vec<(String, Vec<Chunk>)>, DecodeError> {
+    let arr = args.array()?;
+    let mut entries = Vec::with_capacity(arr.len());
+    for entry in arr {
+        let fields = entry.as_array().map(|f| f.as_slice()).unwrap_or_default();
+        match (
+            fields.first().and_then(|v| v.as_str()),
+            fields.get(1).and_then(|v| v.as_array()),
+        ) {
+            (Some(kind), Some(content)) => {
+                entries.push((kind.to_string(), args.decode_chunks(content)?))
+            }
+            _ => return Err(args.wrong_type("an array of [kind, content] entries", entry)),
+        }
+    }
+    Ok(entries)
+}
+
+fn decode_popupmenu_items(args: &mut Args<'_>) -> Result<Vec<PopupmenuItem>, DecodeError> {
+    let arr = args.array()?;
+    let mut items = Vec::with_capacity(arr.len());
+    for item in arr {
+        let fields: Vec<&str> = item
+            .as_array()
+            .map(|fields| fields.iter().filter_map(|f| f.as_str()).collect())
+            .unwrap_or_default();
+        if fields.len() != 4 {
+            return Err(args.wrong_type("an array of [word, kind, menu, info] items", item));
+        }
+        items.push(PopupmenuItem {
+            word: fields[0].to_string(),
+            kind: fields[1].to_string(),
+            menu: fields[2].to_string(),
+            info: fields[3].to_string(),
+        });
+    }
+    Ok(items)
+}
+
+fn decode_event(name: &str, args: &[Value]) -> Result<RedrawEvent, DecodeError> {
+    let mut args = Args::new(name, args);
+    let args = &mut args;
+    Ok(match name {
+        "busy_start" => RedrawEvent::BusyStart,
+        "busy_stop" => RedrawEvent::BusyStop,
+        "cmdline_block_append" => RedrawEvent::CmdlineBlockAppend {
+            line: args.chunks()?,
+        },
+        "cmdline_block_hide" => RedrawEvent::CmdlineBlockHide,
+        "cmdline_block_show" => RedrawEvent::CmdlineBlockShow {
+            lines: args.lines()?,
+        },
+        "cmdline_hide" => RedrawEvent::CmdlineHide,
+        "cmdline_pos" => RedrawEvent::CmdlinePos {
+            pos: args.u64()?,
+            level: args.u64()?,
+        },
+        "cmdline_show" => RedrawEvent::CmdlineShow {
+            content: args.chunks()?,
+            pos: args.u64()?,
+            firstc: args.string()?,
+            prompt: args.string()?,
+            indent: args.u64()?,
+            level: args.u64()?,
+        },
+        "cmdline_special_char" => RedrawEvent::CmdlineSpecialChar {
+            c: args.string()?,
+            shift: args.bool()?,
+            level: args.u64()?,
+        },
+        "default_colors_set" => RedrawEvent::DefaultColorsSet {
+            rgb_fg: args.optional_u64()?,
+            rgb_bg: args.optional_u64()?,
+            rgb_sp: args.optional_u64()?,
+        },
+        "flush" => RedrawEvent::Flush,
+        "grid_clear" => RedrawEvent::GridClear { grid: args.u64()? },
+        "grid_cursor_goto" => RedrawEvent::GridCursorGoto {
+            grid: args.u64()?,
+            row: args.usize()?,
+            column: args.usize()?,
+        },
+        "grid_destroy" => RedrawEvent::GridDestroy { grid: args.u64()? },
+        "grid_line" => RedrawEvent::GridLine {
+            grid: args.u64()?,
+            row: args.usize()?,
+            col_start: args.usize()?,
+            cells: decode_cells(args)?,
+        },
+        "grid_resize" => RedrawEvent::GridResize {
+            grid: args.u64()?,
+            width: args.usize()?,
+            height: args.usize()?,
+        },
+        "grid_scroll" => RedrawEvent::GridScroll {
+            grid: args.u64()?,
+            top: args.usize()?,
+            bot: args.usize()?,
+            left: args.usize()?,
+            right: args.usize()?,
+            rows: args.i64()?,
+            cols: args.i64()?,
+        },
+        "hl_attr_define" => RedrawEvent::HlAttrDefine {
+            id: args.u64()?,
+            attrs: decode_hl_attrs(args)?,
+        },
+        "hl_group_set" => RedrawEvent::HlGroupSet {
+            name: args.string()?,
+            hl_id: args.u64()?,
+        },
+        "mode_change" => RedrawEvent::ModeChange {
+            mode: args.string()?,
+            mode_idx: args.usize()?,
+        },
+        "mode_info_set" => RedrawEvent::ModeInfoSet {
+            cursor_style_enabled: args.bool()?,
+            modes: decode_mode_infos(args)?,
+        },
+        "mouse_off" => RedrawEvent::MouseOff,
+        "mouse_on" => RedrawEvent::MouseOn,
+        "msg_clear" => RedrawEvent::MsgClear,
+        "msg_history_clear" => RedrawEvent::MsgHistoryClear,
+        "msg_history_show" => RedrawEvent::MsgHistoryShow {
+            entries: decode_history_entries(args)?,
+        },
+        "msg_ruler" => RedrawEvent::MsgRuler {
+            content: args.chunks()?,
+        },
+        "msg_show" => RedrawEvent::MsgShow {
+            kind: args.string()?,
+            content: args.chunks()?,
+            replace_last: args.bool()?,
+        },
+        "msg_showcmd" => RedrawEvent::MsgShowcmd {
+            content: args.chunks()?,
+        },
+        "msg_showmode" => RedrawEvent::MsgShowmode {
+            content: args.chunks()?,
+        },
+        "option_set" => RedrawEvent::OptionSet(match args.string()?.as_str() {
+            "guifont" => UiOption::GuiFont(args.string()?),
+            "linespace" => UiOption::LineSpace(args.i64()?),
+            name => UiOption::Other(name.to_string()),
+        }),
+        "popupmenu_hide" => RedrawEvent::PopupmenuHide,
+        "popupmenu_select" => RedrawEvent::PopupmenuSelect {
+            selected: args.i64()?,
+        },
+        "popupmenu_show" => RedrawEvent::PopupmenuShow {
+            items: decode_popupmenu_items(args)?,
+            selected: args.i64()?,
+            row: args.usize()?,
+            col: args.usize()?,
+            grid: args.i64()?,
+        },
+        "set_title" => RedrawEvent::SetTitle {
+            title: args.string()?,
+        },
+        "win_hide" => RedrawEvent::WinHide { grid: args.u64()? },
+        "win_pos" => RedrawEvent::WinPos {
+            grid: args.u64()?,
+            win: args.window()?,
+            start_row: args.usize()?,
+            start_col: args.usize()?,
+            width: args.usize()?,
+            height: args.usize()?,
+        },
+        "win_split" => RedrawEvent::WinSplit {
+            win1: args.window()?,
+            grid1: args.u64()?,
+            win2: args.window()?,
+            grid2: args.u64()?,
+            flags: {
+                let flags = args.u64()?;
+                SplitDirection::try_from(flags)
+                    .map_err(|_| args.invalid(format!("unknown split direction {}", flags)))?
+            },
+        },
+        "win_viewport" => {
+            let grid = args.u64()?;
+            args.window()?;
+            let topline = args.u64()?;
+            // botline, curline, curcol and line_count (neovim 0.6+)
+            for _ in 0..3 {
+                args.u64()?;
+            }
+            args.optional_u64()?;
+            RedrawEvent::WinViewport {
+                grid,
+                topline,
+                scroll_delta: args.optional_i64()?,
+            }
+        }
+        "wildmenu_hide" => RedrawEvent::WildmenuHide,
+        "wildmenu_select" => RedrawEvent::WildmenuSelect {
+            selected: args.i64()?,
+        },
+        "wildmenu_show" => RedrawEvent::WildmenuShow {
+            items: args.strings()?,
+        },
+        // Floating and external windows, and the message grid, aren't supported yet
+        "set_icon"
+        | "win_close"
+        | "win_float_pos"
+        | "win_external_pos"
+        | "win_viewport_margins"
+        | "msg_set_pos" => RedrawEvent::Ignored,
+        _ => return Err(DecodeError::UnknownEvent(name.to_string())),
+    })
+}
+
+/// Decodes one element of a `redraw` notification, i.e. `[event_name, args1, args2, ...]`, into
+/// one result per argument tuple. Errors are reported per tuple so that a single malformed event
+/// doesn't prevent the rest of the batch from being applied.
+pub fn decode_batch(batch: &Value) -> Vec<Result<RedrawEvent, DecodeError>> {
+    let batch = match batch.as_array() {
+        Some(b) => b,
+        None => return vec![Err(DecodeError::NotAnArray(batch.clone()))],
+    };
+    let mut iter = batch.iter();
+    let name = match iter.next() {
+        Some(n) => match n.as_str() {
+            Some(s) => s,
+            None => return vec![Err(DecodeError::NonStringEventName(n.clone()))],
+        },
+        None => return vec![Err(DecodeError::MissingEventName)],
+    };
+    iter.map(|args| match args.as_array() {
+        Some(a) => decode_event(name, a),
+        None => Err(DecodeError::NotAnArray(args.clone())),
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(name: &str, args: Vec<Value>) -> Value {
+        let mut batch = vec![Value::from(name)];
+        batch.push(Value::Array(args));
+        Value::Array(batch)
+    }
+
+    fn decode_one(name: &str, args: Vec<Value>) -> Result<RedrawEvent, DecodeError> {
+        let mut events = decode_batch(&batch(name, args));
+        assert_eq!(events.len(), 1);
+        events.remove(0)
+    }
+
+    #[test]
+    fn grid_line() {
+        let cells = Value::Array(vec![
+            Value::Array(vec![Value::from("a"), Value::from(3)]),
+            Value::Array(vec![Value::from(" "), Value::from(0), Value::from(4)]),
+            Value::Array(vec![Value::from("b")]),
+        ]);
+        let event = decode_one(
+            "grid_line",
+            vec![Value::from(2), Value::from(5), Value::from(1), cells],
+        );
+        match event {
+            Ok(RedrawEvent::GridLine {
+                grid: 2,
+                row: 5,
+                col_start: 1,
+                cells,
+            }) => {
+                let cells: Vec<_> = cells
+                    .iter()
+                    .map(|c| (c.text.as_str(), c.hl_id, c.repeat))
+                    .collect();
+                assert_eq!(
+                    cells,
+                    vec![("a", Some(3), 1), (" ", Some(0), 4), ("b", None, 1)]
+                );
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn batches_hold_one_event_per_argument_list() {
+        let value = Value::Array(vec![
+            Value::from("grid_clear"),
+            Value::Array(vec![Value::from(1)]),
+            Value::Array(vec![Value::from(2)]),
+        ]);
+        let events = decode_batch(&value);
+        assert!(matches!(
+            events.as_slice(),
+            [
+                Ok(RedrawEvent::GridClear { grid: 1 }),
+                Ok(RedrawEvent::GridClear { grid: 2 })
+            ]
+        ));
+    }
+
+    #[test]
+    fn missing_argument() {
+        let event = decode_one("grid_resize", vec![Value::from(2), Value::from(80)]);
+        assert!(matches!(
+            event,
+            Err(DecodeError::MissingArgument { ref event, index: 2 }) if event == "grid_resize"
+        ));
+    }
+
+    #[test]
+    fn wrong_type() {
+        let event = decode_one(
+            "grid_resize",
+            vec![Value::from(2), Value::from("80"), Value::from(20)],
+        );
+        match event {
+            Err(DecodeError::WrongType {
+                event,
+                index: 1,
+                expected,
+                ..
+            }) => {
+                assert_eq!(event, "grid_resize");
+                assert_eq!(expected, "an unsigned integer");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn wrong_cell_type() {
+        let cells = Value::Array(vec![Value::Array(vec![Value::from(1)])]);
+        let event = decode_one(
+            "grid_line",
+            vec![Value::from(2), Value::from(0), Value::from(0), cells],
+        );
+        assert!(matches!(
+            event,
+            Err(DecodeError::WrongType { index: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn unknown_event() {
+        let event = decode_one("no_such_event", vec![]);
+        assert!(
+            matches!(event, Err(DecodeError::UnknownEvent(ref name)) if name == "no_such_event")
+        );
+    }
+
+    #[test]
+    fn known_events_that_are_not_supported_yet() {
+        let events = [
+            "set_icon",
+            "win_close",
+            "win_float_pos",
+            "win_external_pos",
+            "win_viewport_margins",
+            "msg_set_pos",
+        ];
+        for name in &events {
+            assert!(matches!(decode_one(name, vec![]), Ok(RedrawEvent::Ignored)));
+        }
+    }
+
+    #[test]
+    fn malformed_batches() {
+        assert!(matches!(
+            decode_batch(&Value::from(1)).as_slice(),
+            [Err(DecodeError::NotAnArray(_))]
+        ));
+        assert!(matches!(
+            decode_batch(&Value::Array(vec![])).as_slice(),
+            [Err(DecodeError::MissingEventName)]
+        ));
+        assert!(matches!(
+            decode_batch(&Value::Array(vec![Value::from(1)])).as_slice(),
+            [Err(DecodeError::NonStringEventName(_))]
+        ));
+    }
+
+    #[test]
+    fn window_ids() {
+        let win_pos = |win: Value| {
+            let args = vec![
+                Value::from(2),
+                win,
+                Value::from(0),
+                Value::from(0),
+                Value::from(80),
+                Value::from(20),
+            ];
+            match decode_one("win_pos", args) {
+                Ok(RedrawEvent::WinPos { win, .. }) => Ok(win),
+                Ok(other) => panic!("unexpected {:?}", other),
+                Err(e) => Err(e),
+            }
+        };
+        // Older servers send plain integers
+        assert_eq!(win_pos(Value::from(1000)).unwrap(), 1000);
+        assert_eq!(win_pos(Value::Ext(1, vec![0x05])).unwrap(), 5);
+        assert_eq!(
+            win_pos(Value::Ext(1, vec![0xcd, 0x03, 0xe8])).unwrap(),
+            1000
+        );
+        assert!(matches!(
+            win_pos(Value::Ext(0, vec![0x05])),
+            Err(DecodeError::InvalidValue { index: 1, .. })
+        ));
+        assert!(matches!(
+            win_pos(Value::Ext(1, vec![0xcd, 0x03])),
+            Err(DecodeError::InvalidValue { index: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn msgpack_uints() {
+        assert_eq!(decode_msgpack_uint(&[0x7f]), Some(127));
+        assert_eq!(decode_msgpack_uint(&[0xcc, 0xff]), Some(255));
+        assert_eq!(decode_msgpack_uint(&[0xcd, 0x01, 0x00]), Some(256));
+        assert_eq!(
+            decode_msgpack_uint(&[0xce, 0x00, 0x01, 0x00, 0x00]),
+            Some(65536)
+        );
+        assert_eq!(
+            decode_msgpack_uint(&[0xcf, 0, 0, 0, 1, 0, 0, 0, 0]),
+            Some(1 << 32)
+        );
+        // Negative fixint, truncated uint 16 and trailing bytes
+        assert_eq!(decode_msgpack_uint(&[0xff]), None);
+        assert_eq!(decode_msgpack_uint(&[0xcd, 0x01]), None);
+        assert_eq!(decode_msgpack_uint(&[0x01, 0x02]), None);
+        assert_eq!(decode_msgpack_uint(&[]), None);
+    }
+}
@@ -0,0 +1,1463 @@
+use sdl2::pixels::Color;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Point;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::render::RenderTarget;
+use sdl2::render::Texture;
+use sdl2::render::TextureCreator;
+use sdl2::video::{Window, WindowContext};
+use sdl2::VideoSubsystem;
+
+use std::process;
+use std::time::{Duration, Instant};
+
+use crate::atlas::GlyphAtlas;
+use crate::cmdline::Cmdline;
+use crate::fonts::{Fonts, Style};
+use crate::interner::{CellText, Cells, EMPTY, SPACE};
+use crate::messages::Message;
+use crate::popupmenu::{self, Popupmenu};
+use crate::redraw::CursorShape;
+use crate::{
+    AtlasIndexKey, Damage, NvimColumn, NvimGrid, NvimGridId, NvimHighlightAttribute, NvimRow,
+    NvimState,
+};
+
+/// Everything needed to draw an NvimGrid. `T` is the render target: an OS window when running
+/// normally, an offscreen surface when running headless.
+pub struct SDLGrid<T: RenderTarget = Window> {
+    pub canvas: Canvas<T>,
+    pub atlas: GlyphAtlas,
+    pub big_texture: Texture,
+    pub big_texture_copy: Texture,
+    pub texture_creator: TextureCreator<T::Context>,
+    pub width: u32,  // pixels
+    pub height: u32, // pixels
+    pub grid_x_offset: u32,
+    pub grid_y_offset: u32,
+    pub font_width: u32,
+    pub font_height: u32,
+    /// Height of the row kept below the grid for 'showmode', 'showcmd' and 'ruler', 0 if none
+    pub status_height: u32,
+    scroll_animation: Option<ScrollAnimation>,
+}
+
+/// A window scroll being animated. The scrolled region is drawn `offset()` pixels below where it
+/// really is, the offset shrinking to 0 over the duration of the animation. Rows that are not
+/// scrolled in yet are taken from big_texture_copy, which holds the grid as it was before the
+/// scroll.
+struct ScrollAnimation {
+    region: Rect,
+    start_offset: i32,
+    /// How far the last scroll moved the content of the region, in pixels
+    scrolled: i32,
+    started_at: Instant,
+}
+
+impl ScrollAnimation {
+    fn offset(&self, duration: Duration) -> i32 {
+        let progress = self.started_at.elapsed().as_secs_f64() / duration.as_secs_f64();
+        if progress >= 1.0 {
+            0
+        } else {
+            // Ease out: start fast, slow down towards the end
+            (self.start_offset as f64 * (1.0 - progress).powi(3)) as i32
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderDriver {
+    index: u32,
+    name: &'static str,
+}
+
+impl RenderDriver {
+    /// Finds the SDL render driver to use. If the user didn't ask for a specific one, prefer
+    /// opengl, then the software renderer, then whatever SDL has to offer.
+    pub fn find(requested: Option<&str>) -> Result<RenderDriver, String> {
+        let names: Vec<&'static str> = sdl2::render::drivers().map(|d| d.name).collect();
+        let by_name = |wanted: &str| {
+            names
+                .iter()
+                .position(|name| *name == wanted)
+                .map(|index| RenderDriver {
+                    index: index as u32,
+                    name: names[index],
+                })
+        };
+        if let Some(wanted) = requested {
+            return by_name(wanted).ok_or_else(|| {
+                format!(
+                    "Renderer '{}' not available. Available renderers: {}",
+                    wanted,
+                    names.join(", ")
+                )
+            });
+        }
+        by_name("opengl")
+            .or_else(|| by_name("software"))
+            .or_else(|| names.first().map(|name| RenderDriver { index: 0, name }))
+            .ok_or_else(|| "No SDL renderer available.".to_string())
+    }
+}
+
+/// Size in pixels of a window fitting `cells` columns and rows, plus `status_height` pixels
+/// below them for the status strip. Windows the window manager sizes start at 1x1.
+fn window_size(
+    cells: Option<(usize, usize)>,
+    cell_size: (u32, u32),
+    status_height: u32,
+) -> (u32, u32) {
+    match cells {
+        Some((cols, rows)) => (
+            std::cmp::max(1, cols as u32 * cell_size.0),
+            std::cmp::max(1, rows as u32 * cell_size.1 + status_height),
+        ),
+        None => (1, 1),
+    }
+}
+
+/// Creates a resizable OS window and its canvas with `render_driver`. SDL lists the drivers it was
+/// built with even when they can't work, e.g. opengl in VMs or remote X sessions. If the driver
+/// fails, `render_driver` is replaced with the software renderer for this and later windows.
+fn create_canvas(
+    video_subsystem: &VideoSubsystem,
+    render_driver: &mut RenderDriver,
+    title: &str,
+    width: u32,
+    height: u32,
+) -> Result<Canvas<Window>, String> {
+    let build = |driver: &RenderDriver| -> Result<Canvas<Window>, String> {
+        let mut window_builder = video_subsystem.window(title, width, height);
+        if driver.name == "opengl" {
+            window_builder.opengl();
+        }
+        let window = window_builder
+            .resizable()
+            .build()
+            .map_err(|e| e.to_string())?;
+        window
+            .into_canvas()
+            .index(driver.index)
+            .build()
+            .map_err(|e| e.to_string())
+    };
+    match build(render_driver) {
+        Err(e) if render_driver.name != "software" => {
+            let software = RenderDriver::find(Some("software"))?;
+            eprintln!(
+                "Warning: renderer '{}' failed ({}), falling back to 'software'.",
+                render_driver.name, e
+            );
+            *render_driver = software;
+            build(render_driver)
+        }
+        result => result,
+    }
+}
+
+/// Distance of 'showcmd' and 'ruler' from the right edge, in cells, as in neovim
+const SHOWCMD_COLUMNS: usize = 29;
+const RULER_COLUMNS: usize = 18;
+const WHITE: Color = Color::RGBA(255, 255, 255, 255);
+const TRANSPARENT: Color = Color::RGBA(200, 0, 128, 0);
+
+/// Draws the underline, undercurl and strikethrough of the cell at `x`, `y` in `color`.
+#[allow(clippy::too_many_arguments)]
+fn draw_decorations<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    hl_attr: &NvimHighlightAttribute,
+    color: Color,
+    x: i32,
+    y: i32,
+    font_width: u32,
+    font_height: u32,
+    ascent: i32,
+) {
+    if !(hl_attr.underline || hl_attr.undercurl || hl_attr.strikethrough) {
+        return;
+    }
+    let bottom = y + font_height as i32 - 1;
+    let right = x + font_width as i32 - 1;
+    canvas.set_draw_color(color);
+    if hl_attr.underline {
+        let underline_y = std::cmp::min(y + ascent + 1, bottom);
+        canvas
+            .draw_line(Point::new(x, underline_y), Point::new(right, underline_y))
+            .unwrap();
+    }
+    if hl_attr.undercurl {
+        // One period per cell so that the wave is continuous across cells
+        let amplitude = std::cmp::max(1, font_height as i32 / 16);
+        let middle = bottom - amplitude;
+        let points: Vec<Point> = (0..font_width as i32)
+            .map(|i| {
+                let phase = i as f64 * 2.0 * std::f64::consts::PI / font_width as f64;
+                Point::new(
+                    x + i,
+                    middle - (phase.sin() * amplitude as f64).round() as i32,
+                )
+            })
+            .collect();
+        canvas.draw_lines(points.as_slice()).unwrap();
+    }
+    if hl_attr.strikethrough {
+        let strikethrough_y = y + ascent * 2 / 3;
+        canvas
+            .draw_line(
+                Point::new(x, strikethrough_y),
+                Point::new(right, strikethrough_y),
+            )
+            .unwrap();
+    }
+}
+
+/// The slot of the atlas holding the glyph of `key`, rendering it first if needed. `in_cluster`
+/// tells whether `key` is a slice of a shaped cluster, `cell_width` is the width of the cell the
+/// glyph goes in, two cells wide for double-width characters.
+#[allow(clippy::too_many_arguments)]
+fn glyph_slot<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    texture_creator: &TextureCreator<T::Context>,
+    atlas: &mut GlyphAtlas,
+    fonts: &Fonts,
+    state: &NvimState,
+    key: AtlasIndexKey,
+    in_cluster: bool,
+    cell_width: u32,
+    cell_size: (u32, u32),
+    glyph_y: i32,
+) -> Rect {
+    if let Some(slot) = atlas.get(&key) {
+        return slot;
+    }
+    let (glyph_text, style, cluster_index) = key;
+    let (font_width, font_height) = cell_size;
+    let text = state.cell_texts.get(glyph_text);
+    // Clusters were shaped with the primary font
+    let (glyph_font, is_fallback) = if in_cluster {
+        (fonts.get(style), false)
+    } else {
+        fonts.for_char(text.chars().next().unwrap(), style)
+    };
+    let surface = glyph_font
+        .render(text)
+        .blended(WHITE)
+        .map_err(|e| e.to_string())
+        .unwrap();
+    let (surface_width, surface_height) = (surface.width(), surface.height());
+    let glyph = surface.into_canvas().unwrap();
+    let mut texture = texture_creator
+        .create_texture_target(PixelFormatEnum::ARGB8888, surface_width, surface_height)
+        .unwrap();
+    texture
+        .update(
+            None,
+            &glyph.read_pixels(None, PixelFormatEnum::ARGB8888).unwrap(),
+            4 * surface_width as usize,
+        )
+        .unwrap();
+    // Where the glyph goes in its slot. Fallback fonts have different metrics, their glyphs are
+    // shrunk and centered to fit the cell.
+    let (slot_width, glyph_rect) = if is_fallback {
+        let scale = f64::min(
+            1.0,
+            f64::min(
+                cell_width as f64 / surface_width as f64,
+                font_height as f64 / surface_height as f64,
+            ),
+        );
+        let w = std::cmp::max(1, (surface_width as f64 * scale) as u32);
+        let h = std::cmp::max(1, (surface_height as f64 * scale) as u32);
+        (
+            cell_width,
+            Rect::new(
+                (cell_width - w) as i32 / 2,
+                (font_height - h) as i32 / 2,
+                w,
+                h,
+            ),
+        )
+    } else if in_cluster {
+        // The whole cluster is rendered, each of its cells keeps its own part
+        let x = cluster_index as i32 * font_width as i32;
+        (
+            cell_width,
+            Rect::new(-x, glyph_y, surface_width, surface_height),
+        )
+    } else {
+        (
+            surface_width,
+            Rect::new(0, glyph_y, surface_width, surface_height),
+        )
+    };
+    let slot = atlas.allocate(key, slot_width);
+    canvas
+        .with_texture_canvas(&mut atlas.texture, |canvas| {
+            // Glyphs must not spill over neighbouring slots
+            canvas.set_clip_rect(slot);
+            canvas.set_draw_color(TRANSPARENT);
+            canvas.fill_rect(slot).unwrap();
+            let mut to = glyph_rect;
+            to.offset(slot.x(), slot.y());
+            canvas.copy(&texture, None, to).unwrap();
+            canvas.set_clip_rect(None);
+        })
+        .unwrap();
+    // The glyph lives in the atlas from now on
+    unsafe { texture.destroy() };
+    slot
+}
+
+/// Where to draw a popup of `size` cells next to the cell at `anchor`, in a grid of `grid_size`
+/// cells: below the anchor if it fits there or if there's more room below than above, above
+/// otherwise, and shifted left if it would go past the right edge. Returns the row and column of
+/// the top left corner of the popup and the number of rows it gets.
+fn place_popup(
+    anchor: (usize, usize),
+    size: (usize, usize),
+    grid_size: (usize, usize),
+) -> (usize, usize, usize) {
+    let (anchor_row, anchor_col) = anchor;
+    let (rows, cols) = size;
+    let (grid_rows, grid_cols) = grid_size;
+    let below = grid_rows.saturating_sub(anchor_row + 1);
+    let above = anchor_row;
+    let (row, height) = if rows <= below || below >= above {
+        (anchor_row + 1, std::cmp::min(rows, below))
+    } else {
+        let height = std::cmp::min(rows, above);
+        (anchor_row - height, height)
+    };
+    let col = std::cmp::min(anchor_col, grid_cols.saturating_sub(cols));
+    (row, col, height)
+}
+
+/// Draws a line of cells the way grid cells are drawn, in the style and colors of their
+/// highlight unless `colors` gives the background and foreground of every cell.
+#[allow(clippy::too_many_arguments)]
+fn draw_cells<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    texture_creator: &TextureCreator<T::Context>,
+    atlas: &mut GlyphAtlas,
+    fonts: &Fonts,
+    state: &NvimState,
+    cells: &[(CellText, u64)],
+    origin: (i32, i32),
+    cell_size: (u32, u32),
+    glyph_y: i32,
+    colors: Option<(Color, Color)>,
+) {
+    let (x, y) = origin;
+    let (font_width, font_height) = cell_size;
+    let ascent = fonts.regular().ascent() + glyph_y;
+    let default_hl = state.hl_attrs.get(&0);
+    for (i, (text, attr_id)) in cells.iter().enumerate() {
+        // Double-width characters are drawn over the cell that follows them
+        if *text == EMPTY {
+            continue;
+        }
+        let is_wide = cells.get(i + 1).map(|c| c.0) == Some(EMPTY);
+        let cell_width = if is_wide { 2 * font_width } else { font_width };
+        let hl = state.hl_attrs.get(attr_id).or(default_hl);
+        let color = |get: fn(&NvimHighlightAttribute) -> Option<Color>, default: Color| {
+            hl.and_then(get)
+                .or_else(|| default_hl.and_then(get))
+                .unwrap_or(default)
+        };
+        let (bg, fg, style) = match colors {
+            Some((bg, fg)) => (bg, fg, Style::Regular),
+            None => {
+                let bg = color(|hl| hl.background, Color::BLACK);
+                let fg = color(|hl| hl.foreground, WHITE);
+                let (style, reverse) = hl.map_or((Style::Regular, false), |hl| {
+                    (Style::new(hl.bold, hl.italic), hl.reverse)
+                });
+                if reverse {
+                    (fg, bg, style)
+                } else {
+                    (bg, fg, style)
+                }
+            }
+        };
+        let cell_x = x + (i as i32) * (font_width as i32);
+        canvas.set_draw_color(bg);
+        canvas
+            .fill_rect(Rect::new(cell_x, y, cell_width, font_height))
+            .unwrap();
+        let slot = glyph_slot(
+            canvas,
+            texture_creator,
+            atlas,
+            fonts,
+            state,
+            (*text, style, 0),
+            false,
+            cell_width,
+            cell_size,
+            glyph_y,
+        );
+        atlas.texture.set_color_mod(fg.r, fg.g, fg.b);
+        canvas
+            .copy(
+                &atlas.texture,
+                slot,
+                Rect::new(cell_x, y, slot.width(), slot.height()),
+            )
+            .unwrap();
+        if let (None, Some(hl)) = (colors, hl) {
+            let sp = color(|hl| hl.special, fg);
+            draw_decorations(canvas, hl, sp, cell_x, y, cell_width, font_height, ascent);
+        }
+    }
+}
+
+/// Draws a line of cells `cols` cells wide, cutting the cells that don't fit and filling the rest
+/// with the default background.
+#[allow(clippy::too_many_arguments)]
+fn draw_line<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    texture_creator: &TextureCreator<T::Context>,
+    atlas: &mut GlyphAtlas,
+    fonts: &Fonts,
+    state: &NvimState,
+    cells: &[(CellText, u64)],
+    cols: usize,
+    origin: (i32, i32),
+    cell_size: (u32, u32),
+    glyph_y: i32,
+) {
+    let (x, y) = origin;
+    let (font_width, font_height) = cell_size;
+    let default_bg = state
+        .hl_attrs
+        .get(&0)
+        .and_then(|hl| hl.background)
+        .unwrap_or(Color::BLACK);
+    canvas.set_draw_color(default_bg);
+    canvas
+        .fill_rect(Rect::new(x, y, cols as u32 * font_width, font_height))
+        .unwrap();
+    draw_cells(
+        canvas,
+        texture_creator,
+        atlas,
+        fonts,
+        state,
+        fit(cells, cols),
+        origin,
+        cell_size,
+        glyph_y,
+        None,
+    );
+}
+
+/// The first cells of `cells` that fit in `cols` cells, without the first half of a
+/// double-width character that would be cut.
+fn fit(cells: &[(CellText, u64)], cols: usize) -> &[(CellText, u64)] {
+    let mut end = std::cmp::min(cells.len(), cols);
+    if end > 0 && end < cells.len() && cells[end].0 == EMPTY {
+        end -= 1;
+    }
+    &cells[..end]
+}
+
+/// Draws 'showmode' on the left of a row of `cols` cells, and 'showcmd' and 'ruler' on the right,
+/// where neovim puts them when it draws the message area itself.
+#[allow(clippy::too_many_arguments)]
+fn draw_status<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    texture_creator: &TextureCreator<T::Context>,
+    atlas: &mut GlyphAtlas,
+    fonts: &Fonts,
+    state: &NvimState,
+    cols: usize,
+    origin: (i32, i32),
+    cell_size: (u32, u32),
+    glyph_y: i32,
+) {
+    let mut cells = vec![(SPACE, 0); cols];
+    let mut put = |content: &[(CellText, u64)], start: usize| {
+        let content = fit(content, cols.saturating_sub(start));
+        cells.splice(start..start + content.len(), content.iter().copied());
+    };
+    put(&state.showmode, 0);
+    put(&state.showcmd, cols.saturating_sub(SHOWCMD_COLUMNS));
+    put(&state.ruler, cols.saturating_sub(RULER_COLUMNS));
+    draw_line(
+        canvas,
+        texture_creator,
+        atlas,
+        fonts,
+        state,
+        &cells,
+        cols,
+        origin,
+        cell_size,
+        glyph_y,
+    );
+}
+
+/// The lines of `messages` wrapped after `cols` cells, the parts neovim didn't highlight getting
+/// the highlight of their message's kind.
+fn message_lines<'a>(
+    state: &NvimState,
+    messages: impl Iterator<Item = &'a Message>,
+    cols: usize,
+) -> Vec<Cells> {
+    let mut lines = vec![];
+    for message in messages {
+        let default_attr = message
+            .hl_group()
+            .and_then(|group| state.hl_groups.get(group))
+            .copied()
+            .unwrap_or(0);
+        lines.extend(message.cells(default_attr, cols));
+    }
+    lines
+}
+
+/// Draws the lines of the current cmdline block followed by the cmdline itself at the top of the
+/// grid, scrolled horizontally so that the cursor stays visible.
+#[allow(clippy::too_many_arguments)]
+fn draw_cmdline<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    texture_creator: &TextureCreator<T::Context>,
+    atlas: &mut GlyphAtlas,
+    fonts: &Fonts,
+    state: &NvimState,
+    cmdline: &Cmdline,
+    grid_cols: usize,
+    origin: (i32, i32),
+    cell_size: (u32, u32),
+    glyph_y: i32,
+) {
+    let (font_width, font_height) = cell_size;
+    let (x, mut y) = origin;
+    let default_hl = state.hl_attrs.get(&0);
+    let default_bg = default_hl
+        .and_then(|hl| hl.background)
+        .unwrap_or(Color::BLACK);
+    let default_fg = default_hl.and_then(|hl| hl.foreground).unwrap_or(WHITE);
+    let (cells, cursor) = cmdline.cells();
+    let scroll = cmdline.scroll(grid_cols);
+    for line in &state.cmdline_block {
+        draw_line(
+            canvas,
+            texture_creator,
+            atlas,
+            fonts,
+            state,
+            line,
+            grid_cols,
+            (x, y),
+            cell_size,
+            glyph_y,
+        );
+        y += font_height as i32;
+    }
+    draw_line(
+        canvas,
+        texture_creator,
+        atlas,
+        fonts,
+        state,
+        &cells[std::cmp::min(scroll, cells.len())..],
+        grid_cols,
+        (x, y),
+        cell_size,
+        glyph_y,
+    );
+
+    if !state.cursor_blink_visible() {
+        return;
+    }
+    let mode = state.current_mode();
+    let cursor_x = x + ((cursor - scroll) as i32) * (font_width as i32);
+    let cursor_cells = if cells.get(cursor + 1).map(|c| c.0) == Some(EMPTY) {
+        2
+    } else {
+        1
+    };
+    let cell_width = cursor_cells as u32 * font_width;
+    let percentage = |size: u32| {
+        let p = mode.map(|m| m.cell_percentage).unwrap_or(100) as u32;
+        std::cmp::max(1, size * std::cmp::min(p, 100) / 100)
+    };
+    let cursor_rect = match mode.map(|m| m.cursor_shape) {
+        Some(CursorShape::Vertical) => Rect::new(cursor_x, y, percentage(font_width), font_height),
+        Some(CursorShape::Horizontal) => {
+            let h = percentage(font_height);
+            Rect::new(cursor_x, y + (font_height - h) as i32, cell_width, h)
+        }
+        _ => Rect::new(cursor_x, y, cell_width, font_height),
+    };
+    canvas.set_draw_color(default_fg);
+    canvas.fill_rect(cursor_rect).unwrap();
+    // Thinner cursors leave the character visible
+    if cursor_rect.width() == cell_width && cursor_rect.height() == font_height {
+        let end = std::cmp::min(cursor + cursor_cells, cells.len());
+        draw_cells(
+            canvas,
+            texture_creator,
+            atlas,
+            fonts,
+            state,
+            &cells[std::cmp::min(cursor, end)..end],
+            (cursor_x, y),
+            cell_size,
+            glyph_y,
+            Some((default_fg, default_bg)),
+        );
+    }
+}
+
+/// Draws the completion menu below or above `anchor`, with the word, kind and menu of each item
+/// in columns, a scrollbar when not all items fit and the info of the selected item on the side.
+#[allow(clippy::too_many_arguments)]
+fn draw_popupmenu<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    texture_creator: &TextureCreator<T::Context>,
+    atlas: &mut GlyphAtlas,
+    fonts: &Fonts,
+    state: &NvimState,
+    menu: &Popupmenu,
+    anchor: (usize, usize),
+    grid_size: (usize, usize),
+    origin: (i32, i32),
+    cell_size: (u32, u32),
+    glyph_y: i32,
+) {
+    if menu.items.is_empty() {
+        return;
+    }
+    let default_hl = state.hl_attrs.get(&0);
+    let default_bg = default_hl
+        .and_then(|hl| hl.background)
+        .unwrap_or(Color::BLACK);
+    let default_fg = default_hl.and_then(|hl| hl.foreground).unwrap_or(WHITE);
+    // Without the Pmenu highlight groups, the menu uses the default colors reversed and the
+    // selected item the default colors
+    let colors = |group: &str, bg: Color, fg: Color| {
+        let hl = state.hl_group(group);
+        (
+            hl.and_then(|hl| hl.background).unwrap_or(bg),
+            hl.and_then(|hl| hl.foreground).unwrap_or(fg),
+        )
+    };
+    let (menu_bg, menu_fg) = colors("Pmenu", default_fg, default_bg);
+    let (selected_bg, selected_fg) = colors("PmenuSel", default_bg, default_fg);
+    let (sbar_bg, _) = colors("PmenuSbar", menu_bg, menu_fg);
+    let (thumb_bg, _) = colors("PmenuThumb", menu_fg, menu_bg);
+
+    let column_width = |field: usize| {
+        menu.items
+            .iter()
+            .map(|item| item.fields[field].len())
+            .max()
+            .unwrap_or(0)
+    };
+    let word_width = std::cmp::min(column_width(0), 50);
+    let kind_width = std::cmp::min(column_width(1), 20);
+    let menu_width = std::cmp::min(column_width(2), 30);
+    // (first cell, width, field) of each column. There's one cell of padding on each side and
+    // between columns, the last one is for the scrollbar.
+    let mut columns = vec![(1, word_width, 0)];
+    for (field, width) in [(1, kind_width), (2, menu_width)].iter() {
+        if *width > 0 {
+            let (start, previous_width, _) = columns[columns.len() - 1];
+            columns.push((start + previous_width + 1, *width, *field));
+        }
+    }
+    let (last_start, last_width, _) = columns[columns.len() - 1];
+    let menu_cols = std::cmp::min(last_start + last_width + 1, grid_size.1);
+    let wanted_rows = std::cmp::min(menu.items.len(), popupmenu::MAX_HEIGHT);
+    // The menu starts one cell before the completed word, so that the words are aligned
+    let (row, col, height) = place_popup(
+        (anchor.0, anchor.1.saturating_sub(1)),
+        (wanted_rows, menu_cols),
+        grid_size,
+    );
+    if height == 0 {
+        return;
+    }
+    let (font_width, font_height) = cell_size;
+    let x = origin.0 + (col as i32) * (font_width as i32);
+    let y = origin.1 + (row as i32) * (font_height as i32);
+    let menu_rect = Rect::new(
+        x,
+        y,
+        menu_cols as u32 * font_width,
+        height as u32 * font_height,
+    );
+    canvas.set_draw_color(menu_bg);
+    canvas.fill_rect(menu_rect).unwrap();
+    let visible = menu.visible(height);
+    for (line, index) in visible.clone().enumerate() {
+        let item = &menu.items[index];
+        let item_y = y + (line as i32) * (font_height as i32);
+        let item_colors = if menu.selected == Some(index) {
+            canvas.set_draw_color(selected_bg);
+            canvas
+                .fill_rect(Rect::new(x, item_y, menu_rect.width(), font_height))
+                .unwrap();
+            (selected_bg, selected_fg)
+        } else {
+            (menu_bg, menu_fg)
+        };
+        for (start, width, field) in &columns {
+            let text_x = x + (*start as i32) * (font_width as i32);
+            // Columns that don't fit in the grid are cut
+            let max_width = std::cmp::min(*width, menu_cols.saturating_sub(*start));
+            draw_cells(
+                canvas,
+                texture_creator,
+                atlas,
+                fonts,
+                state,
+                fit(&item.fields[*field], max_width),
+                (text_x, item_y),
+                cell_size,
+                glyph_y,
+                Some(item_colors),
+            );
+        }
+    }
+    if visible.len() < menu.items.len() {
+        let sbar_x = menu_rect.right() - font_width as i32;
+        canvas.set_draw_color(sbar_bg);
+        canvas
+            .fill_rect(Rect::new(sbar_x, y, font_width, menu_rect.height()))
+            .unwrap();
+        let thumb_height = std::cmp::max(1, height * height / menu.items.len());
+        let thumb_row = visible.start * height / menu.items.len();
+        canvas.set_draw_color(thumb_bg);
+        canvas
+            .fill_rect(Rect::new(
+                sbar_x,
+                y + (thumb_row as i32) * (font_height as i32),
+                font_width,
+                thumb_height as u32 * font_height,
+            ))
+            .unwrap();
+    }
+
+    // The info of the selected item goes next to the menu, on the side with the most room
+    let lines = match menu.selected.map(|s| &menu.items[s].info) {
+        Some(info) if !info.is_empty() => info,
+        _ => return,
+    };
+    let info_cols = lines.iter().map(|l| l.len()).max().unwrap_or(0) + 2;
+    let right_room = grid_size.1.saturating_sub(col + menu_cols);
+    let (info_col, info_cols) = if right_room >= col {
+        (col + menu_cols, std::cmp::min(info_cols, right_room))
+    } else {
+        let info_cols = std::cmp::min(info_cols, col);
+        (col - info_cols, info_cols)
+    };
+    if info_cols < 3 {
+        return;
+    }
+    let info_rows = std::cmp::min(lines.len(), grid_size.0.saturating_sub(row));
+    let info_x = origin.0 + (info_col as i32) * (font_width as i32);
+    canvas.set_draw_color(menu_bg);
+    canvas
+        .fill_rect(Rect::new(
+            info_x,
+            y,
+            info_cols as u32 * font_width,
+            info_rows as u32 * font_height,
+        ))
+        .unwrap();
+    for (i, line) in lines.iter().take(info_rows).enumerate() {
+        draw_cells(
+            canvas,
+            texture_creator,
+            atlas,
+            fonts,
+            state,
+            fit(line, info_cols - 2),
+            (
+                info_x + font_width as i32,
+                y + (i as i32) * (font_height as i32),
+            ),
+            cell_size,
+            glyph_y,
+            Some((menu_bg, menu_fg)),
+        );
+    }
+}
+
+/// The title OS windows are created with. The window manager uses it to find the window of a
+/// grid before it has been marked, see wm::WindowManager::mark_window(). It contains nwin's PID
+/// since not every window manager can tell the windows of several nwin instances apart otherwise.
+pub fn window_title(id: NvimGridId) -> String {
+    format!("Nwin {} - Grid {}", process::id(), id)
+}
+
+const MESSAGES_WINDOW_TITLE: &str = "Nwin - Messages";
+
+fn messages_atlas(
+    canvas: &Canvas<Window>,
+    texture_creator: &TextureCreator<WindowContext>,
+    cell_size: (u32, u32),
+) -> GlyphAtlas {
+    let info = canvas.info();
+    GlyphAtlas::new(
+        texture_creator,
+        info.max_texture_width,
+        info.max_texture_height,
+        cell_size.0,
+        cell_size.1,
+    )
+}
+
+/// The OS window messages are shown in when they don't go over the cursor grid.
+pub struct MessagesWindow {
+    pub canvas: Canvas<Window>,
+    texture_creator: TextureCreator<WindowContext>,
+    atlas: GlyphAtlas,
+    /// The cell size the glyphs of the atlas were rendered for
+    cell_size: (u32, u32),
+}
+
+impl MessagesWindow {
+    /// The window is made big enough for `cells` columns and rows of `cell_size` pixels, or left
+    /// for the window manager to size.
+    pub fn new(
+        video_subsystem: &VideoSubsystem,
+        render_driver: &mut RenderDriver,
+        cells: Option<(usize, usize)>,
+        cell_size: (u32, u32),
+    ) -> MessagesWindow {
+        let (width, height) = window_size(cells, cell_size, 0);
+        let canvas = create_canvas(
+            video_subsystem,
+            render_driver,
+            MESSAGES_WINDOW_TITLE,
+            width,
+            height,
+        )
+        .unwrap();
+        let texture_creator = canvas.texture_creator();
+        let atlas = messages_atlas(&canvas, &texture_creator, cell_size);
+        MessagesWindow {
+            canvas,
+            texture_creator,
+            atlas,
+            cell_size,
+        }
+    }
+
+    /// Draws the message history followed by the prompts neovim is waiting for an answer to, or
+    /// the output of :messages while it's shown. The most recent lines are at the bottom.
+    pub fn draw(&mut self, state: &NvimState, fonts: &Fonts) {
+        let default_bg = match state.hl_attrs.get(&0).and_then(|hl| hl.background) {
+            Some(color) => color,
+            None => return,
+        };
+        let (font_width, font_height) = match fonts.cell_size(state.linespace) {
+            Ok(size) => size,
+            Err(_) => return,
+        };
+        if (font_width, font_height) != self.cell_size {
+            self.cell_size = (font_width, font_height);
+            self.atlas = messages_atlas(&self.canvas, &self.texture_creator, self.cell_size);
+        }
+        let (width, height) = self.canvas.window().size();
+        let cols = (width / font_width) as usize;
+        let rows = (height / font_height) as usize;
+        self.canvas.set_draw_color(default_bg);
+        self.canvas.clear();
+        let messages = &state.messages;
+        let lines = if messages.history_shown.is_empty() {
+            let prompts = messages.shown.iter().filter(|m| m.is_prompt());
+            message_lines(state, messages.history.iter().chain(prompts), cols)
+        } else {
+            message_lines(state, messages.history_shown.iter(), cols)
+        };
+        let first = lines.len().saturating_sub(rows);
+        let y = ((rows - (lines.len() - first)) as i32) * (font_height as i32);
+        for (row, line) in lines[first..].iter().enumerate() {
+            draw_line(
+                &mut self.canvas,
+                &self.texture_creator,
+                &mut self.atlas,
+                fonts,
+                state,
+                line,
+                cols,
+                (0, y + (row as i32) * (font_height as i32)),
+                (font_width, font_height),
+                (state.linespace / 2) as i32,
+            );
+        }
+        self.canvas.present();
+    }
+}
+
+impl SDLGrid<Window> {
+    pub fn new(
+        video_subsystem: &VideoSubsystem,
+        render_driver: &mut RenderDriver,
+        id: NvimGridId,
+        cells: Option<(usize, usize)>,
+        status_row: bool,
+        font_width: u32,
+        font_height: u32,
+    ) -> SDLGrid {
+        let title = window_title(id);
+        // Like resize(), which takes the status row out of the window's height
+        let status_height = if status_row { font_height } else { 0 };
+        let (window_width, window_height) =
+            window_size(cells, (font_width, font_height), status_height);
+        let canvas = create_canvas(
+            video_subsystem,
+            render_driver,
+            &title,
+            window_width,
+            window_height,
+        )
+        .unwrap();
+        // The textures start at 1x1 whatever the size of the window, so that the first frame
+        // resizes them and lets neovim know how many cells fit
+        let width = 1;
+        let height = 1;
+        let texture_creator = canvas.texture_creator();
+        SDLGrid::from_canvas(
+            canvas,
+            texture_creator,
+            width,
+            height,
+            font_width,
+            font_height,
+        )
+    }
+}
+
+impl<T: RenderTarget> SDLGrid<T> {
+    pub fn from_canvas(
+        canvas: Canvas<T>,
+        texture_creator: TextureCreator<T::Context>,
+        width: u32,
+        height: u32,
+        font_width: u32,
+        font_height: u32,
+    ) -> SDLGrid<T> {
+        let big_texture = texture_creator
+            .create_texture_target(PixelFormatEnum::ARGB8888, width, height)
+            .unwrap();
+        let big_texture_copy = texture_creator
+            .create_texture_target(PixelFormatEnum::ARGB8888, width, height)
+            .unwrap();
+        let info = canvas.info();
+        let atlas = GlyphAtlas::new(
+            &texture_creator,
+            info.max_texture_width,
+            info.max_texture_height,
+            font_width,
+            font_height,
+        );
+        SDLGrid {
+            canvas,
+            atlas,
+            big_texture,
+            big_texture_copy,
+            texture_creator,
+            width,
+            height,
+            grid_x_offset: 0,
+            grid_y_offset: 0,
+            font_width,
+            font_height,
+            status_height: 0,
+            scroll_animation: None,
+        }
+    }
+
+    /// The cell under the pixel at `x`, `y` of the window.
+    pub fn cell_at(&self, x: i32, y: i32) -> (NvimRow, NvimColumn) {
+        let column = std::cmp::max(0, x - self.grid_x_offset as i32) as u32 / self.font_width;
+        let row = std::cmp::max(0, y - self.grid_y_offset as i32) as u32 / self.font_height;
+        (row as NvimRow, column as NvimColumn)
+    }
+
+    /// Switches to a new cell size, e.g. because 'guifont' changed. Glyphs will be rendered again,
+    /// callers are responsible for resizing the grid and damaging it.
+    pub fn set_font_size(&mut self, font_width: u32, font_height: u32) {
+        let info = self.canvas.info();
+        self.atlas = GlyphAtlas::new(
+            &self.texture_creator,
+            info.max_texture_width,
+            info.max_texture_height,
+            font_width,
+            font_height,
+        );
+        self.font_width = font_width;
+        self.font_height = font_height;
+    }
+
+    /// Resizes the textures backing the grid to `new_width`x`new_height` pixels, keeping what was
+    /// already drawn. A row is kept at the bottom for the status strip if `status_row` is set.
+    /// Returns the number of columns and rows that fit in the new size.
+    pub fn resize(
+        &mut self,
+        new_width: u32,
+        new_height: u32,
+        background: Color,
+        status_row: bool,
+    ) -> (u32, u32) {
+        let SDLGrid {
+            canvas,
+            big_texture,
+            big_texture_copy,
+            texture_creator,
+            width,
+            height,
+            grid_x_offset,
+            grid_y_offset,
+            font_width,
+            font_height,
+            status_height,
+            ..
+        } = self;
+        *status_height = if status_row { *font_height } else { 0 };
+        let grid_height = new_height.saturating_sub(*status_height);
+        let col_count = new_width / *font_width;
+        let row_count = grid_height / *font_height;
+        let pixel_grid_width = col_count * *font_width;
+        let pixel_grid_height = row_count * *font_height;
+        let new_x_offset = (new_width - pixel_grid_width) / 2;
+        let new_y_offset = (grid_height - pixel_grid_height) / 2;
+        let min_width = std::cmp::min(new_width, *width);
+        let min_height = std::cmp::min(new_height, *height);
+        // back up big_texture to big_texture_copy
+        let backup_rectangle = Rect::new(0, 0, min_width, min_height);
+        canvas
+            .with_texture_canvas(big_texture_copy, |canvas| {
+                let from = Rect::new(
+                    *grid_x_offset as i32,
+                    *grid_y_offset as i32,
+                    min_width,
+                    min_height,
+                );
+                canvas.copy(big_texture, from, backup_rectangle).unwrap();
+            })
+            .unwrap();
+        // deallocate big_texture
+        // drop(big_texture);
+        // allocate new big_texture
+        *big_texture = texture_creator
+            .create_texture_target(None, new_width, new_height)
+            .unwrap();
+        // restore backup
+        canvas
+            .with_texture_canvas(big_texture, |canvas| {
+                canvas.set_draw_color(background);
+                canvas.clear();
+                let to = Rect::new(
+                    new_x_offset as i32,
+                    new_y_offset as i32,
+                    min_width,
+                    min_height,
+                );
+                canvas.copy(big_texture_copy, backup_rectangle, to).unwrap();
+            })
+            .unwrap();
+        // destroy backup buffer
+        // drop(big_texture_copy);
+        // allocate new backup buffer
+        *big_texture_copy = texture_creator
+            .create_texture_target(None, new_width, new_height)
+            .unwrap();
+        *width = new_width;
+        *height = new_height;
+        *grid_x_offset = new_x_offset;
+        *grid_y_offset = new_y_offset;
+        // big_texture_copy doesn't hold the grid anymore
+        self.scroll_animation = None;
+        (col_count, row_count)
+    }
+
+    /// Applies the damages of `grid` to big_texture and copies the result to the canvas, along
+    /// with the cursor, cmdline and messages if `grid` is the grid the cursor is in. Window scrolls
+    /// are animated over `smooth_scroll`.
+    pub fn draw(
+        &mut self,
+        grid: &NvimGrid,
+        is_cursor_grid: bool,
+        state: &NvimState,
+        fonts: &Fonts,
+        smooth_scroll: Duration,
+    ) {
+        let (default_bg, default_fg, default_sp) =
+            if let Some(default_hl) = state.hl_attrs.get(&0) {
+                (
+                    default_hl.background,
+                    default_hl.foreground,
+                    default_hl.special,
+                )
+            } else {
+                return;
+            };
+        let font = fonts.regular();
+        // 'linespace' is split between the top and the bottom of cells
+        let glyph_y = (state.linespace / 2) as i32;
+        let ascent = font.ascent() + glyph_y;
+        let SDLGrid {
+            canvas,
+            atlas,
+            big_texture,
+            big_texture_copy,
+            texture_creator,
+            width,
+            height,
+            grid_x_offset,
+            grid_y_offset,
+            font_width,
+            font_height,
+            status_height,
+            scroll_animation,
+            ..
+        } = self;
+        if grid.get_width() > 0 && grid.get_height() > 0 {
+            for d in &grid.damages {
+                if let Damage::Cell {
+                    row,
+                    column,
+                    width,
+                    height,
+                } = d
+                {
+                    let damage_top = *row;
+                    let mut damage_bottom = row + height;
+                    if damage_bottom > grid.get_height() {
+                        damage_bottom = grid.get_height();
+                    }
+                    for current_row in damage_top..damage_bottom {
+                        let damage_left = *column;
+                        let mut damage_right = column + width;
+                        if damage_right > grid.get_width() {
+                            damage_right = grid.get_width();
+                        }
+                        for current_column in damage_left..damage_right {
+                            let text = grid.cells[current_row][current_column];
+                            // Double-width characters are drawn over the cell that follows them
+                            if text == EMPTY {
+                                continue;
+                            }
+                            let is_wide = current_column + 1 < grid.get_width()
+                                && grid.cells[current_row][current_column + 1] == EMPTY;
+                            let cell_width = if is_wide {
+                                2 * *font_width
+                            } else {
+                                *font_width
+                            };
+                            let attr_id = grid.colors[current_row][current_column];
+                            let hl_attr = state.hl_attrs.get(&attr_id).unwrap();
+                            let style = Style::new(hl_attr.bold, hl_attr.italic);
+                            let (glyph_text, cluster_index) =
+                                grid.glyph(current_row, current_column);
+                            let in_cluster = grid.clusters[current_row][current_column].is_some();
+                            let atlas_key = (glyph_text, style, cluster_index);
+                            let slot = glyph_slot(
+                                canvas,
+                                texture_creator,
+                                atlas,
+                                fonts,
+                                state,
+                                atlas_key,
+                                in_cluster,
+                                cell_width,
+                                (*font_width, *font_height),
+                                glyph_y,
+                            );
+                            canvas
+                                .with_texture_canvas(big_texture, |canvas| {
+                                    let mut bg = hl_attr
+                                        .background
+                                        .or_else(|| default_bg)
+                                        .unwrap();
+                                    let mut fg = hl_attr
+                                        .foreground
+                                        .or_else(|| default_fg)
+                                        .unwrap();
+                                    if hl_attr.reverse {
+                                        let tmp = bg;
+                                        bg = fg;
+                                        fg = tmp;
+                                    }
+
+                                    let x = (*grid_x_offset as i32)
+                                        + (current_column as i32) * (*font_width as i32);
+                                    let y = (*grid_y_offset as i32)
+                                        + (current_row as i32) * (*font_height as i32);
+                                    canvas.set_draw_color(bg);
+                                    canvas
+                                        .fill_rect(Rect::new(x, y, cell_width, *font_height))
+                                        .unwrap();
+                                    let to = Rect::new(x, y, slot.width(), slot.height());
+                                    atlas.texture.set_color_mod(fg.r, fg.g, fg.b);
+                                    canvas.copy(&atlas.texture, slot, to).unwrap();
+                                    draw_decorations(
+                                        canvas,
+                                        hl_attr,
+                                        hl_attr.special.or(default_sp).unwrap_or(fg),
+                                        x,
+                                        y,
+                                        cell_width,
+                                        *font_height,
+                                        ascent,
+                                    );
+                                })
+                                .unwrap();
+                        }
+                    }
+                } else if let Damage::Scroll {
+                    from_row,
+                    from_col,
+                    to_row,
+                    to_col,
+                    width,
+                    height,
+                } = d
+                {
+                    canvas
+                        .with_texture_canvas(big_texture_copy, |canvas| {
+                            canvas.copy(&big_texture, None, None).unwrap();
+                        })
+                        .unwrap();
+                    let cells_rect = |row: NvimRow, column: NvimColumn| {
+                        Rect::new(
+                            (*grid_x_offset as i32) + (column as i32) * (*font_width as i32),
+                            (*grid_y_offset as i32) + (row as i32) * (*font_height as i32),
+                            (*width as u32) * *font_width,
+                            (*height as u32) * *font_height,
+                        )
+                    };
+                    let f = cells_rect(*from_row, *from_col);
+                    let t = cells_rect(*to_row, *to_col);
+                    canvas
+                        .with_texture_canvas(big_texture, |canvas| {
+                            canvas.copy(&big_texture_copy, f, t).unwrap();
+                        })
+                        .unwrap();
+                    // Only vertical scrolls are animated
+                    if grid.viewport_scrolled
+                        && from_col == to_col
+                        && smooth_scroll > Duration::from_secs(0)
+                    {
+                        let top = std::cmp::min(*from_row, *to_row);
+                        let bottom = std::cmp::min(
+                            std::cmp::max(*from_row, *to_row) + height,
+                            grid.get_height(),
+                        );
+                        let region = Rect::new(
+                            (*grid_x_offset as i32) + (*from_col as i32) * (*font_width as i32),
+                            (*grid_y_offset as i32) + (top as i32) * (*font_height as i32),
+                            (*width as u32) * *font_width,
+                            ((bottom - top) as u32) * *font_height,
+                        );
+                        let scrolled =
+                            (*from_row as i32 - *to_row as i32) * (*font_height as i32);
+                        // Scrolling again before the end of the animation continues from where
+                        // the region is currently drawn
+                        let current_offset = scroll_animation
+                            .as_ref()
+                            .filter(|a| a.region == region)
+                            .map_or(0, |a| a.offset(smooth_scroll));
+                        let max_offset = region.height() as i32;
+                        *scroll_animation = Some(ScrollAnimation {
+                            region,
+                            start_offset: std::cmp::max(
+                                -max_offset,
+                                std::cmp::min(max_offset, current_offset + scrolled),
+                            ),
+                            scrolled,
+                            started_at: Instant::now(),
+                        });
+                    } else {
+                        // big_texture_copy was overwritten
+                        *scroll_animation = None;
+                    }
+                }
+            }
+            let r = Rect::new(0, 0, *width, *height);
+            canvas.copy(&big_texture, r, r).unwrap();
+            // Pixels the scrolled region is drawn away from its real position
+            let mut scroll_offset = 0;
+            let mut scroll_region = None;
+            if let Some(animation) = scroll_animation.as_ref() {
+                scroll_offset = animation.offset(smooth_scroll);
+                let region = animation.region;
+                scroll_region = Some(region);
+                canvas.set_clip_rect(region);
+                canvas.set_draw_color(default_bg.unwrap());
+                canvas.fill_rect(region).unwrap();
+                let mut old = region;
+                old.offset(0, scroll_offset - animation.scrolled);
+                canvas.copy(&big_texture_copy, region, old).unwrap();
+                let mut new = region;
+                new.offset(0, scroll_offset);
+                canvas.copy(&big_texture, region, new).unwrap();
+                canvas.set_clip_rect(None);
+            }
+            if scroll_offset == 0 {
+                *scroll_animation = None;
+            }
+
+            if is_cursor_grid {
+                if let Some(cmdline) = state.cmdline.as_ref() {
+                    draw_cmdline(
+                        canvas,
+                        texture_creator,
+                        atlas,
+                        fonts,
+                        state,
+                        cmdline,
+                        grid.get_width(),
+                        (*grid_x_offset as i32, *grid_y_offset as i32),
+                        (*font_width, *font_height),
+                        glyph_y,
+                    );
+                } else if state.cursor_on && state.cursor_blink_visible() {
+                    let (row, column) = grid.get_cursor_pos();
+                    let attr_id = grid.colors[row as usize][column as usize];
+                    let mode = state.current_mode();
+                    // The Cursor highlight group, if any, gives the colors of the cursor.
+                    // Otherwise the colors of the cell under the cursor are reversed.
+                    let cursor_attr = mode
+                        .filter(|m| m.attr_id != 0)
+                        .and_then(|m| state.hl_attrs.get(&m.attr_id));
+                    if let Some(hl_attr) = state.hl_attrs.get(&attr_id) {
+                        let (cursor_color, text_color) = match cursor_attr {
+                            Some(cursor_attr) => (
+                                cursor_attr.background.or(default_fg).unwrap(),
+                                cursor_attr.foreground.or(default_bg).unwrap(),
+                            ),
+                            None => (
+                                hl_attr.foreground.or(default_fg).unwrap(),
+                                hl_attr.background.or(default_bg).unwrap(),
+                            ),
+                        };
+                        let x = (*grid_x_offset as i32) + (column as i32) * (*font_width as i32);
+                        let mut y = (*grid_y_offset as i32) + (row as i32) * (*font_height as i32);
+                        // The cursor moves along with the text it's on
+                        if scroll_region.map_or(false, |r| r.contains_point((x, y))) {
+                            y += scroll_offset;
+                        }
+                        let (text, cluster_index) = grid.glyph(row, column);
+                        let cell_width = if column + 1 < grid.get_width()
+                            && grid.cells[row][column + 1] == EMPTY
+                        {
+                            2 * *font_width
+                        } else {
+                            *font_width
+                        };
+                        let percentage = |size: u32| {
+                            let p = mode.map(|m| m.cell_percentage).unwrap_or(100) as u32;
+                            std::cmp::max(1, size * std::cmp::min(p, 100) / 100)
+                        };
+                        let cursor_rect = match mode.map(|m| m.cursor_shape) {
+                            Some(CursorShape::Vertical) => {
+                                Rect::new(x, y, percentage(*font_width), *font_height)
+                            }
+                            Some(CursorShape::Horizontal) => {
+                                let h = percentage(*font_height);
+                                Rect::new(x, y + (*font_height - h) as i32, cell_width, h)
+                            }
+                            _ => Rect::new(x, y, cell_width, *font_height),
+                        };
+                        canvas.set_draw_color(cursor_color);
+                        canvas.fill_rect(cursor_rect).unwrap();
+                        // Thinner cursors leave the character visible
+                        if cursor_rect.width() == cell_width && cursor_rect.height() == *font_height
+                        {
+                            let style = Style::new(hl_attr.bold, hl_attr.italic);
+                            let key = (text, style, cluster_index);
+                            if let Some(slot) = atlas.get(&key) {
+                                let texture = &mut atlas.texture;
+                                texture.set_color_mod(text_color.r, text_color.g, text_color.b);
+                                let to = Rect::new(x, y, slot.width(), slot.height());
+                                canvas.copy(texture, slot, to).unwrap();
+                            }
+                        }
+                    }
+                }
+                // Windows shorter than the status row, e.g. not mapped yet, have no room for it
+                if *status_height > 0 && *height >= *status_height {
+                    let y = *height - *status_height;
+                    draw_status(
+                        canvas,
+                        texture_creator,
+                        atlas,
+                        fonts,
+                        state,
+                        grid.get_width(),
+                        (*grid_x_offset as i32, y as i32),
+                        (*font_width, *font_height),
+                        glyph_y,
+                    );
+                }
+                if let Some(menu) = state.popupmenu.as_ref() {
+                    // Completion in the cmdline is anchored to the cmdline, drawn below the
+                    // lines of the current block. Menus of other grids are anchored to the
+                    // cursor.
+                    let anchor = if menu.grid < 0 {
+                        let col = state.cmdline.as_ref().map_or(0, |cmdline| {
+                            let scroll = cmdline.scroll(grid.get_width());
+                            cmdline.column(menu.col).saturating_sub(scroll)
+                        });
+                        (state.cmdline_block.len(), col)
+                    } else if menu.grid as NvimGridId == state.cursor_grid {
+                        (menu.row, menu.col)
+                    } else {
+                        grid.get_cursor_pos()
+                    };
+                    draw_popupmenu(
+                        canvas,
+                        texture_creator,
+                        atlas,
+                        fonts,
+                        state,
+                        menu,
+                        anchor,
+                        (grid.get_height(), grid.get_width()),
+                        (*grid_x_offset as i32, *grid_y_offset as i32),
+                        (*font_width, *font_height),
+                        glyph_y,
+                    );
+                }
+                if !state.messages.windowed {
+                    let messages = &state.messages;
+                    let lines = message_lines(
+                        state,
+                        messages.history_shown.iter().chain(messages.shown.iter()),
+                        grid.get_width(),
+                    );
+                    // The most recent lines, when there are more than the grid can show
+                    let first = lines.len().saturating_sub(grid.get_height());
+                    for (row, line) in lines[first..].iter().enumerate() {
+                        draw_line(
+                            canvas,
+                            texture_creator,
+                            atlas,
+                            fonts,
+                            state,
+                            line,
+                            grid.get_width(),
+                            (
+                                *grid_x_offset as i32,
+                                *grid_y_offset as i32 + (row as i32) * (*font_height as i32),
+                            ),
+                            (*font_width, *font_height),
+                            glyph_y,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use harfrust::{Direction, FontRef, ShaperData, UnicodeBuffer};
+
+use crate::fonts::{face_path, find_font, FontSpec, Style};
+use crate::interner::{CellText, Interner, EMPTY, SPACE};
+use crate::ClusterSlice;
+
+/// First SDL_ttf version that can shape text with HarfBuzz. nwin only finds clusters, SDL_ttf
+/// renders them, so ligatures are only drawn if SDL_ttf shapes them too.
+const SHAPING_SDL_TTF: (u8, u8, u8) = (2, 0, 18);
+
+struct Face {
+    data: Vec<u8>,
+    shaper_data: ShaperData,
+}
+
+impl Face {
+    fn load(path: &Path) -> Result<Face, String> {
+        let data = fs::read(path).map_err(|e| e.to_string())?;
+        let shaper_data = ShaperData::new(&FontRef::new(&data).map_err(|e| e.to_string())?);
+        Ok(Face { data, shaper_data })
+    }
+}
+
+/// Shapes the text of grid lines with the primary font, in order to find the cells that can't be
+/// drawn one at a time: ligatures (e.g. `->` in Fira Code) and characters whose glyph depends on
+/// their neighbours (e.g. Arabic).
+pub struct Shaper {
+    /// The faces of the primary font, styles without a face of their own are synthesized by
+    /// SDL_ttf from the regular face and shaped with it
+    faces: HashMap<Style, Face>,
+    /// Glyphs of cell texts shaped on their own, to compare them with the glyphs they get in
+    /// context.
+    isolated: HashMap<(CellText, Style), Vec<u32>>,
+}
+
+impl Shaper {
+    pub fn load(spec: &FontSpec) -> Result<Shaper, String> {
+        let version = sdl2::ttf::get_linked_version();
+        if (version.major, version.minor, version.patch) < SHAPING_SDL_TTF {
+            return Err(format!(
+                "SDL_ttf {} can't render ligatures, 2.0.18 or later is needed",
+                version
+            ));
+        }
+        let path = find_font(&spec.family)
+            .ok_or_else(|| format!("Can't find font '{}', check README.md", spec.family))?;
+        let mut faces = HashMap::new();
+        faces.insert(Style::Regular, Face::load(&path)?);
+        let styles = [
+            (Style::Bold, "Bold"),
+            (Style::Italic, "Italic"),
+            (Style::BoldItalic, "BoldItalic"),
+        ];
+        for (style, face) in styles.iter() {
+            if let Some(face_path) = face_path(&path, face) {
+                faces.insert(*style, Face::load(&face_path)?);
+            }
+        }
+        Ok(Shaper {
+            faces,
+            isolated: HashMap::new(),
+        })
+    }
+
+    /// Shapes `text` in `style` and returns its glyphs along with the byte offset of the cluster
+    /// they belong to.
+    fn shape(&self, text: &str, style: Style) -> Vec<(u32, u32)> {
+        let face = self
+            .faces
+            .get(&style)
+            .unwrap_or_else(|| &self.faces[&Style::Regular]);
+        let font = FontRef::new(&face.data).unwrap();
+        let shaper = face.shaper_data.shaper(&font).build();
+        let mut buffer = UnicodeBuffer::new();
+        buffer.push_str(text);
+        // Neovim lays out right-to-left text from left to right too
+        buffer.set_direction(Direction::LeftToRight);
+        buffer.guess_segment_properties();
+        shaper
+            .shape(buffer, &[])
+            .glyph_infos()
+            .iter()
+            .map(|info| (info.glyph_id, info.cluster))
+            .collect()
+    }
+
+    fn isolated_glyphs(&mut self, text: CellText, style: Style, interner: &Interner) -> &[u32] {
+        let key = (text, style);
+        if !self.isolated.contains_key(&key) {
+            let glyphs = self
+                .shape(interner.get(text), style)
+                .iter()
+                .map(|g| g.0)
+                .collect();
+            self.isolated.insert(key, glyphs);
+        }
+        &self.isolated[&key]
+    }
+
+    /// Shapes the runs of cells of a grid line that share a highlight, in the style of their
+    /// highlight, and records in `clusters` the cells that have to be drawn together.
+    pub fn shape_line(
+        &mut self,
+        texts: &[CellText],
+        colors: &[u64],
+        styles: &[Style],
+        clusters: &mut [Option<ClusterSlice>],
+        interner: &mut Interner,
+    ) {
+        for cluster in clusters.iter_mut() {
+            *cluster = None;
+        }
+        // Double-width characters and spaces never join their neighbours, runs stop there
+        let breaks_run = |column: usize| {
+            texts[column] == EMPTY
+                || texts[column] == SPACE
+                || texts.get(column + 1) == Some(&EMPTY)
+        };
+        let mut start = 0;
+        while start < texts.len() {
+            let mut end = start;
+            while end < texts.len() && !breaks_run(end) && colors[end] == colors[start] {
+                end += 1;
+            }
+            if end - start > 1 {
+                let style = styles[start];
+                self.shape_run(start, &texts[start..end], style, clusters, interner);
+            }
+            start = std::cmp::max(end, start + 1);
+        }
+    }
+
+    fn shape_run(
+        &mut self,
+        start: usize,
+        texts: &[CellText],
+        style: Style,
+        clusters: &mut [Option<ClusterSlice>],
+        interner: &mut Interner,
+    ) {
+        let mut text = String::new();
+        // Byte offset of the text of each cell in `text`
+        let mut offsets = Vec::with_capacity(texts.len());
+        for cell in texts {
+            offsets.push(text.len() as u32);
+            text.push_str(interner.get(*cell));
+        }
+        let cell_of = |cluster: u32| match offsets.binary_search(&cluster) {
+            Ok(cell) => cell,
+            Err(cell) => cell - 1,
+        };
+        let glyphs = self.shape(&text, style);
+        let mut glyphs_of_cell = vec![vec![]; texts.len()];
+        for (glyph, cluster) in &glyphs {
+            glyphs_of_cell[cell_of(*cluster)].push(*glyph);
+        }
+        // A cell is contextual when its glyphs differ from the ones it gets on its own, or when
+        // it has no glyph because a ligature starting in a previous cell swallowed it.
+        let mut contextual = vec![false; texts.len()];
+        for (cell, glyphs) in glyphs_of_cell.iter().enumerate() {
+            if glyphs.is_empty() {
+                contextual[cell] = true;
+                if cell > 0 {
+                    contextual[cell - 1] = true;
+                }
+            } else if glyphs.as_slice() != self.isolated_glyphs(texts[cell], style, interner) {
+                contextual[cell] = true;
+            }
+        }
+        let mut cell = 0;
+        while cell < texts.len() {
+            let mut end = cell;
+            while end < texts.len() && contextual[end] {
+                end += 1;
+            }
+            if end - cell > 1 {
+                let cluster_text: String =
+                    texts[cell..end].iter().map(|t| interner.get(*t)).collect();
+                let id = interner.intern_cluster(&cluster_text);
+                for (index, column) in (cell..end).enumerate() {
+                    clusters[start + column] = Some((id, index as u16));
+                }
+            }
+            cell = std::cmp::max(end, cell + 1);
+        }
+    }
+}
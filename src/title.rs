@@ -0,0 +1,116 @@
+use neovim_lib::{Neovim, NeovimApi, Value};
+
+use crate::{NvimGrid, NvimState, NvimWinId};
+
+pub const DEFAULT_TEMPLATE: &str = "%f [%m] - nwin";
+
+/// Expands `template` for the window displayed in `grid`:
+/// - `%f` is the name of the window's buffer, relative to the current directory if possible,
+/// - `%m` is `+` if the buffer is modified and nothing otherwise. `[%m]` is left out along with
+///   the space before it for unmodified buffers, so that `%f [%m]` gives `name [+]` or `name`,
+/// - `%t` is the title neovim would give its window (see `:help 'titlestring'`), only known when
+///   the 'title' option is set,
+/// - `%%` is a literal `%`.
+pub fn expand(template: &str, grid: &NvimGrid, nvim_title: &str) -> String {
+    let mut title = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c == '[' && !grid.modified && chars.as_str().starts_with("%m]") {
+            chars.nth(2);
+            let rest = chars.as_str();
+            if title.ends_with(' ') && (rest.is_empty() || rest.starts_with(' ')) {
+                title.pop();
+            }
+            continue;
+        }
+        if c != '%' {
+            title.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('f') if grid.buffer_name.is_empty() => title.push_str("[No Name]"),
+            Some('f') => title.push_str(&grid.buffer_name),
+            Some('m') if grid.modified => title.push('+'),
+            Some('m') => {}
+            Some('t') => title.push_str(nvim_title),
+            Some(c) => title.push(c),
+            None => title.push('%'),
+        }
+    }
+    title
+}
+
+/// Asks neovim for the name and modified flag of the buffer displayed in each window. This is
+/// done in a single request since it happens every time a buffer changes.
+pub fn refresh_buffer_info(nvim: &mut Neovim, state: &mut NvimState) {
+    let windows: Vec<NvimWinId> = state
+        .grids
+        .values()
+        .filter(|g| g.window_id != 0)
+        .map(|g| g.window_id)
+        .collect();
+    if windows.is_empty() {
+        return;
+    }
+    let expr = format!(
+        "map([{}], {{_, w -> [fnamemodify(bufname(winbufnr(w)), ':~:.'), \
+         getbufvar(winbufnr(w), '&modified')]}})",
+        windows
+            .iter()
+            .map(|w| w.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+    let infos = match nvim.eval(&expr) {
+        Ok(Value::Array(infos)) => infos,
+        Ok(v) => {
+            eprintln!("Unexpected buffer info: {}", v);
+            return;
+        }
+        Err(e) => {
+            eprintln!("Failed to get buffer info: {}", e);
+            return;
+        }
+    };
+    for (window, info) in windows.into_iter().zip(infos) {
+        let (name, modified) = match info.as_array().map(|a| a.as_slice()) {
+            Some([name, modified]) => (
+                name.as_str().unwrap_or("").to_string(),
+                modified.as_u64().unwrap_or(0) != 0,
+            ),
+            _ => continue,
+        };
+        for grid in state.grids.values_mut().filter(|g| g.window_id == window) {
+            grid.buffer_name = name.clone();
+            grid.modified = modified;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(name: &str, modified: bool) -> NvimGrid {
+        let mut grid = NvimGrid::new(1, 1);
+        grid.buffer_name = name.to_string();
+        grid.modified = modified;
+        grid
+    }
+
+    #[test]
+    fn modified_flag_goes_between_the_template_brackets() {
+        let expand = |grid: &NvimGrid| expand(DEFAULT_TEMPLATE, grid, "");
+        assert_eq!(expand(&grid("main.rs", true)), "main.rs [+] - nwin");
+        assert_eq!(expand(&grid("main.rs", false)), "main.rs - nwin");
+        assert_eq!(expand(&grid("", false)), "[No Name] - nwin");
+    }
+
+    #[test]
+    fn other_placeholders() {
+        let unmodified = grid("a", false);
+        assert_eq!(expand("%f%m [%m]", &unmodified, ""), "a");
+        assert_eq!(expand("%t 100%% %x%", &unmodified, "nvim"), "nvim 100% x%");
+        assert_eq!(expand("%f%m", &grid("a", true), ""), "a+");
+    }
+}
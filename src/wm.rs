@@ -0,0 +1,454 @@
+use std::env;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+use swayipc::{Connection, NodeLayout};
+
+use crate::NvimGridId;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    SplitH,
+    SplitV,
+    Stacked,
+    Tabbed,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Split {
+    Horizontal,
+    Vertical,
+}
+
+/// A window manager container holding an nwin window.
+#[derive(Debug)]
+pub struct Container {
+    pub mark: String,
+    /// Layout of the container's parent, i.e. how the container is arranged with its siblings.
+    pub parent_layout: Layout,
+}
+
+/// The layout operations nwin needs in order to mirror neovim's window layout.
+pub trait WindowManager {
+    /// Tags the window titled `title` that belongs to process `pid` with `mark`. Returns false if
+    /// there is no such window, e.g. because the window manager hasn't mapped it yet.
+    fn mark_window(&mut self, pid: u32, title: &str, mark: &str) -> Result<bool, String>;
+    fn find_by_mark(&mut self, mark: &str) -> Option<Container>;
+    fn split(&mut self, container: &Container, split: Split) -> Result<(), String>;
+    fn set_tabbed(&mut self, container: &Container) -> Result<(), String>;
+    fn focus(&mut self, container: &Container) -> Result<(), String>;
+}
+
+/// The mark identifying the container of `grid`. Marks are unique across nwin instances since
+/// they contain nwin's PID.
+pub fn grid_mark(grid: NvimGridId) -> String {
+    format!("nwin-{}-{}", process::id(), grid)
+}
+
+fn criteria(container: &Container, command: &str) -> String {
+    format!("[con_mark=\"^{}$\"] {}", container.mark, command)
+}
+
+fn split_command(split: Split) -> &'static str {
+    match split {
+        Split::Horizontal => "splith",
+        Split::Vertical => "splitv",
+    }
+}
+
+/// Connects to sway if SWAYSOCK is set, to i3 otherwise.
+pub fn connect() -> Result<Box<dyn WindowManager>, String> {
+    if env::var_os("SWAYSOCK").is_some() {
+        return Ok(Box::new(Sway::connect()?));
+    }
+    Ok(Box::new(I3::connect_from_env()?))
+}
+
+pub struct Sway {
+    connection: Connection,
+}
+
+impl Sway {
+    pub fn connect() -> Result<Sway, String> {
+        Connection::new()
+            .map(|connection| Sway { connection })
+            .map_err(|e| format!("Can't connect to sway: {}", e))
+    }
+
+    fn run(&mut self, command: String) -> Result<(), String> {
+        let outcomes = self
+            .connection
+            .run_command(command)
+            .map_err(|e| e.to_string())?;
+        for outcome in outcomes {
+            outcome.map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+impl WindowManager for Sway {
+    fn mark_window(&mut self, pid: u32, title: &str, mark: &str) -> Result<bool, String> {
+        let window = self
+            .connection
+            .get_tree()
+            .map_err(|e| e.to_string())?
+            .find(|n| {
+                n.pid == Some(pid as i32) && n.name.as_deref() == Some(title) && n.marks.is_empty()
+            });
+        match window {
+            Some(window) => self
+                .run(format!("[con_id={}] mark --add {}", window.id, mark))
+                .map(|_| true),
+            None => Ok(false),
+        }
+    }
+    fn find_by_mark(&mut self, mark: &str) -> Option<Container> {
+        let parent_layout = sway_parent_layout(self.connection.get_tree().ok()?, mark)?;
+        Some(Container {
+            mark: mark.to_string(),
+            parent_layout,
+        })
+    }
+    fn split(&mut self, container: &Container, split: Split) -> Result<(), String> {
+        self.run(criteria(container, split_command(split)))
+    }
+    fn set_tabbed(&mut self, container: &Container) -> Result<(), String> {
+        self.run(criteria(container, "layout tabbed"))
+    }
+    fn focus(&mut self, container: &Container) -> Result<(), String> {
+        self.run(criteria(container, "focus"))
+    }
+}
+
+/// Layout of the parent of the container marked with `mark`, which can be tiled or floating.
+fn sway_parent_layout(tree: swayipc::Node, mark: &str) -> Option<Layout> {
+    let has_mark = |n: &swayipc::Node| n.marks.iter().any(|m| m == mark);
+    let parent = tree.find(|node| {
+        node.nodes
+            .iter()
+            .chain(node.floating_nodes.iter())
+            .any(has_mark)
+    })?;
+    Some(match parent.layout {
+        NodeLayout::SplitH => Layout::SplitH,
+        NodeLayout::SplitV => Layout::SplitV,
+        NodeLayout::Stacked => Layout::Stacked,
+        NodeLayout::Tabbed => Layout::Tabbed,
+        _ => Layout::Other,
+    })
+}
+
+/// A minimal client for i3's IPC protocol, see https://i3wm.org/docs/ipc.html
+pub struct I3 {
+    stream: UnixStream,
+}
+
+const I3_IPC_MAGIC: &[u8] = b"i3-ipc";
+const I3_IPC_RUN_COMMAND: u32 = 0;
+const I3_IPC_GET_TREE: u32 = 4;
+
+impl I3 {
+    pub fn connect(path: &Path) -> Result<I3, String> {
+        UnixStream::connect(path)
+            .map(|stream| I3 { stream })
+            .map_err(|e| format!("Can't connect to i3 at {}: {}", path.display(), e))
+    }
+
+    /// Uses I3SOCK, or asks i3 where its socket is if I3SOCK isn't set.
+    pub fn connect_from_env() -> Result<I3, String> {
+        let path = match env::var_os("I3SOCK") {
+            Some(path) => PathBuf::from(path),
+            None => {
+                let output = Command::new("i3")
+                    .arg("--get-socketpath")
+                    .output()
+                    .map_err(|e| format!("Can't find i3's socket: {}", e))?;
+                if !output.status.success() {
+                    return Err("Neither sway nor i3 seem to be running.".to_string());
+                }
+                PathBuf::from(String::from_utf8_lossy(&output.stdout).trim())
+            }
+        };
+        I3::connect(&path)
+    }
+
+    fn message(&mut self, kind: u32, payload: &str) -> Result<serde_json::Value, String> {
+        let mut message = I3_IPC_MAGIC.to_vec();
+        message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(payload.as_bytes());
+        self.stream.write_all(&message).map_err(|e| e.to_string())?;
+
+        let mut header = [0u8; 14];
+        self.stream
+            .read_exact(&mut header)
+            .map_err(|e| e.to_string())?;
+        if &header[0..6] != I3_IPC_MAGIC {
+            return Err("Invalid i3 IPC reply".to_string());
+        }
+        let mut length = [0u8; 4];
+        length.copy_from_slice(&header[6..10]);
+        let mut body = vec![0u8; u32::from_ne_bytes(length) as usize];
+        self.stream
+            .read_exact(&mut body)
+            .map_err(|e| e.to_string())?;
+        serde_json::from_slice(&body).map_err(|e| e.to_string())
+    }
+
+    fn run(&mut self, command: &str) -> Result<(), String> {
+        let reply = self.message(I3_IPC_RUN_COMMAND, command)?;
+        // The reply contains one {"success": bool, "error": string} object per command
+        for outcome in reply.as_array().map(|a| a.as_slice()).unwrap_or(&[]) {
+            if outcome["success"] != serde_json::Value::Bool(true) {
+                return Err(outcome["error"]
+                    .as_str()
+                    .unwrap_or("unknown error")
+                    .to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the first node of the tree matching `predicate`, along with the layout of its parent.
+fn find_in_i3_tree<'a>(
+    node: &'a serde_json::Value,
+    predicate: &dyn Fn(&serde_json::Value) -> bool,
+) -> Option<(&'a serde_json::Value, Layout)> {
+    let children = ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node[*key].as_array())
+        .flatten();
+    for child in children.clone() {
+        if predicate(child) {
+            let parent_layout = match node["layout"].as_str() {
+                Some("splith") => Layout::SplitH,
+                Some("splitv") => Layout::SplitV,
+                Some("stacked") => Layout::Stacked,
+                Some("tabbed") => Layout::Tabbed,
+                _ => Layout::Other,
+            };
+            return Some((child, parent_layout));
+        }
+    }
+    children
+        .filter_map(|child| find_in_i3_tree(child, predicate))
+        .next()
+}
+
+fn i3_marks(node: &serde_json::Value) -> &[serde_json::Value] {
+    node["marks"]
+        .as_array()
+        .map(|a| a.as_slice())
+        .unwrap_or(&[])
+}
+
+impl WindowManager for I3 {
+    /// i3 doesn't know which process windows belong to, so this relies on the title only, which
+    /// contains nwin's PID.
+    fn mark_window(&mut self, _pid: u32, title: &str, mark: &str) -> Result<bool, String> {
+        let tree = self.message(I3_IPC_GET_TREE, "")?;
+        let window = find_in_i3_tree(&tree, &|n| {
+            n["name"].as_str() == Some(title) && i3_marks(n).is_empty()
+        });
+        match window.and_then(|(window, _)| window["id"].as_i64()) {
+            Some(id) => self
+                .run(&format!("[con_id={}] mark --add {}", id, mark))
+                .map(|_| true),
+            None => Ok(false),
+        }
+    }
+    fn find_by_mark(&mut self, mark: &str) -> Option<Container> {
+        let tree = self.message(I3_IPC_GET_TREE, "").ok()?;
+        let (_, parent_layout) =
+            find_in_i3_tree(&tree, &|n| i3_marks(n).iter().any(|m| m == mark))?;
+        Some(Container {
+            mark: mark.to_string(),
+            parent_layout,
+        })
+    }
+    fn split(&mut self, container: &Container, split: Split) -> Result<(), String> {
+        self.run(&criteria(container, split_command(split)))
+    }
+    fn set_tabbed(&mut self, container: &Container) -> Result<(), String> {
+        self.run(&criteria(container, "layout tabbed"))
+    }
+    fn focus(&mut self, container: &Container) -> Result<(), String> {
+        self.run(&criteria(container, "focus"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+    use std::fs;
+    use std::os::unix::net::UnixListener;
+    use std::thread::{self, JoinHandle};
+
+    /// Listens on a fresh socket and answers the messages of the first client with `replies`, in
+    /// order, each reply starting with `magic`. Returns the type and payload of the messages.
+    fn fake_i3(
+        name: &str,
+        magic: &'static [u8],
+        replies: Vec<&'static str>,
+    ) -> (PathBuf, JoinHandle<Vec<(u32, String)>>) {
+        let path = env::temp_dir().join(format!("nwin-{}-{}.sock", process::id(), name));
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let socket_path = path.clone();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            fs::remove_file(socket_path).unwrap();
+            let mut received = vec![];
+            for reply in replies {
+                let mut header = [0u8; 14];
+                stream.read_exact(&mut header).unwrap();
+                assert_eq!(&header[0..6], I3_IPC_MAGIC);
+                let length = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+                let kind = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+                let mut payload = vec![0u8; length as usize];
+                stream.read_exact(&mut payload).unwrap();
+                received.push((kind, String::from_utf8(payload).unwrap()));
+
+                let mut message = magic.to_vec();
+                message.extend_from_slice(&(reply.len() as u32).to_ne_bytes());
+                message.extend_from_slice(&kind.to_ne_bytes());
+                message.extend_from_slice(reply.as_bytes());
+                stream.write_all(&message).unwrap();
+            }
+            received
+        });
+        (path, server)
+    }
+
+    const TREE: &str = r#"{
+        "id": 1, "layout": "splith", "marks": [], "floating_nodes": [],
+        "nodes": [
+            {"id": 5, "name": "Nwin 1 - Grid 2", "marks": ["nwin-1-2"], "nodes": []},
+            {"id": 6, "layout": "tabbed", "marks": [], "nodes": [
+                {"id": 7, "name": "Nwin 1 - Grid 2", "marks": [], "nodes": []},
+                {"id": 8, "name": "Nwin 1 - Grid 4", "marks": ["nwin-1-4"], "nodes": []}
+            ]}
+        ]
+    }"#;
+
+    #[test]
+    fn mark_window_marks_the_unmarked_window_with_the_title() {
+        let (path, server) = fake_i3("mark", I3_IPC_MAGIC, vec![TREE, r#"[{"success": true}]"#]);
+        let mut i3 = I3::connect(&path).unwrap();
+        assert_eq!(i3.mark_window(1, "Nwin 1 - Grid 2", "nwin-1-3"), Ok(true));
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                (I3_IPC_GET_TREE, String::new()),
+                (
+                    I3_IPC_RUN_COMMAND,
+                    "[con_id=7] mark --add nwin-1-3".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn mark_window_without_matching_window() {
+        let (path, server) = fake_i3("unmapped", I3_IPC_MAGIC, vec![TREE]);
+        let mut i3 = I3::connect(&path).unwrap();
+        assert_eq!(i3.mark_window(1, "Nwin 1 - Grid 9", "nwin-1-9"), Ok(false));
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn find_by_mark_reports_the_parent_layout() {
+        let (path, server) = fake_i3("find", I3_IPC_MAGIC, vec![TREE, TREE, TREE]);
+        let mut i3 = I3::connect(&path).unwrap();
+        let layout = |i3: &mut I3, mark| i3.find_by_mark(mark).map(|c| c.parent_layout);
+        assert_eq!(layout(&mut i3, "nwin-1-2"), Some(Layout::SplitH));
+        assert_eq!(layout(&mut i3, "nwin-1-4"), Some(Layout::Tabbed));
+        assert_eq!(layout(&mut i3, "nwin-1-5"), None);
+        server.join().unwrap();
+    }
+
+    /// Parses `json` as a sway tree, adding the fields swayipc requires but the tests don't need.
+    fn sway_tree(json: &str) -> swayipc::Node {
+        fn complete(node: &mut serde_json::Value) {
+            let rect = serde_json::json!({"x": 0, "y": 0, "width": 0, "height": 0});
+            let defaults = serde_json::json!({
+                "type": "con", "border": "none", "current_border_width": 0, "layout": "none",
+                "orientation": "none", "percent": null, "rect": rect, "window_rect": rect,
+                "deco_rect": rect, "geometry": rect, "urgent": false, "focused": false,
+                "focus": [], "sticky": false, "marks": [], "nodes": [], "floating_nodes": []
+            });
+            let node = node.as_object_mut().unwrap();
+            for (key, value) in defaults.as_object().unwrap() {
+                node.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+            for key in &["nodes", "floating_nodes"] {
+                for child in node[*key].as_array_mut().unwrap() {
+                    complete(child);
+                }
+            }
+        }
+        let mut tree = serde_json::from_str(json).unwrap();
+        complete(&mut tree);
+        serde_json::from_value(tree).unwrap()
+    }
+
+    #[test]
+    fn sway_parent_layout_finds_tiled_and_floating_windows() {
+        let tree = || {
+            sway_tree(
+                r#"{
+                "id": 1, "type": "root", "layout": "splith", "nodes": [
+                    {"id": 2, "type": "workspace", "layout": "splitv", "nodes": [
+                        {"id": 5, "marks": ["nwin-1-2"]},
+                        {"id": 6, "layout": "tabbed", "nodes": [
+                            {"id": 8, "marks": ["nwin-1-4"]}
+                        ]}
+                    ], "floating_nodes": [
+                        {"id": 9, "type": "floating_con", "marks": ["nwin-1-6"]}
+                    ]}
+                ]
+            }"#,
+            )
+        };
+        assert_eq!(sway_parent_layout(tree(), "nwin-1-2"), Some(Layout::SplitV));
+        assert_eq!(sway_parent_layout(tree(), "nwin-1-4"), Some(Layout::Tabbed));
+        assert_eq!(sway_parent_layout(tree(), "nwin-1-6"), Some(Layout::SplitV));
+        assert_eq!(sway_parent_layout(tree(), "nwin-1-5"), None);
+    }
+
+    #[test]
+    fn failed_commands_return_i3_errors() {
+        let (path, server) = fake_i3(
+            "error",
+            I3_IPC_MAGIC,
+            vec![r#"[{"success": false, "error": "No window matches"}]"#],
+        );
+        let mut i3 = I3::connect(&path).unwrap();
+        let container = Container {
+            mark: "nwin-1-2".to_string(),
+            parent_layout: Layout::SplitH,
+        };
+        assert_eq!(i3.focus(&container), Err("No window matches".to_string()));
+        assert_eq!(
+            server.join().unwrap(),
+            vec![(
+                I3_IPC_RUN_COMMAND,
+                "[con_mark=\"^nwin-1-2$\"] focus".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn replies_without_the_magic_string_are_errors() {
+        let (path, server) = fake_i3("magic", b"i4-ipc", vec!["[]"]);
+        let mut i3 = I3::connect(&path).unwrap();
+        assert!(i3.message(I3_IPC_GET_TREE, "").is_err());
+        server.join().unwrap();
+    }
+}